@@ -0,0 +1,125 @@
+use std::time::{Duration, SystemTime};
+use matrix_sdk::Client;
+use matrix_sdk::config::SyncSettings;
+use matrix_sdk::room::{Joined, MessagesOptions};
+use matrix_sdk::ruma::{MilliSecondsSinceUnixEpoch, UInt, UserId};
+use matrix_sdk::ruma::events::{AnySyncMessageLikeEvent, AnySyncTimelineEvent};
+use matrix_sdk::ruma::serde::Raw;
+use crate::data::event::find_event_in_db;
+use crate::data::room_setting::find_room_settings_in_db;
+use crate::event_handler::{EventHandler, ReactionOutcome};
+use crate::utils::mention_util::MentionMessage;
+use crate::utils::room_config_util::get_switch_setting;
+
+/// Upper bound for the pagination of a single room, with 100 events per page
+const MAX_PAGES_PER_ROOM: usize = 50;
+
+/// Syncs once from the stored sync token and handles everything that happened while the bot was offline.
+/// If the server had to leave out events of a room, its timeline is paginated back to the last handled event.
+/// All missed reactions of a room are applied in the order they were sent, with the cooldowns evaluated at the time
+/// of each reaction, and a single summary is sent per room. Returns the next_batch token to continue syncing from
+pub async fn catch_up(client: &Client, event_handler: &EventHandler, sync_token: String, event_retention_days: u64) -> anyhow::Result<String> {
+    let response = client.sync_once(SyncSettings::default().token(sync_token)).await?;
+
+    // Events older than this were pruned from the event table, so they cannot be recognized as handled anymore
+    let cutoff = SystemTime::now() - Duration::from_secs(event_retention_days * 24 * 60 * 60);
+
+    for (room_id, joined_room) in &response.rooms.join {
+        let room = match client.get_joined_room(room_id) {
+            Some(room) => room,
+            None => continue,
+        };
+
+        let mut events: Vec<AnySyncMessageLikeEvent> = joined_room.timeline.events.iter()
+            .filter_map(|event| to_message_like_event(&event.event))
+            .collect();
+
+        if joined_room.timeline.limited {
+            events.extend(paginate_to_last_handled_event(event_handler, &room, joined_room.timeline.prev_batch.clone(), cutoff).await);
+        }
+
+        // Reactions have to be applied in the order they were sent, otherwise the cooldowns would be wrong
+        events.sort_by_key(|event| event.origin_server_ts());
+
+        let mut changes = Vec::new();
+        let mut cooldown_count = 0;
+        for event in &events {
+            match event_handler.catch_up_event(event, &room).await {
+                Some(ReactionOutcome::Applied(change)) => changes.push(change),
+                Some(ReactionOutcome::Cooldown(_)) | Some(ReactionOutcome::OverBudget { .. }) => cooldown_count += 1,
+                _ => {},
+            }
+        }
+
+        if changes.is_empty() && cooldown_count == 0 {
+            continue;
+        }
+        println!("Caught up with {} missed reactions in room {}", changes.len() + cooldown_count, room_id);
+
+        let mut message = MentionMessage::default();
+        let heading = format!("While I was offline {} Social Credit changes happened:", changes.len());
+        message.push(&heading, &format!("<b>{}</b>", heading));
+        for change in &changes {
+            message.push("\n", "<br>");
+            change.push_to(&mut message);
+        }
+        if cooldown_count > 0 {
+            let text = format!("{} reactions were ignored because of the cooldown or the budget", cooldown_count);
+            message.push(&format!("\n{}", text), &format!("<br>{}", text));
+        }
+
+        let ping = get_switch_setting(&find_room_settings_in_db(event_handler.conn(), room_id.as_str()), "ping_changes", true);
+        let ping_user_ids: Vec<&UserId> = if ping { changes.iter().map(|change| change.recipient_id.as_ref()).collect() } else { Vec::new() };
+        message.send(&room, &ping_user_ids).await;
+    }
+
+    Ok(response.next_batch)
+}
+
+/// Loads older events of the room, starting at the prev_batch token of the sync, until an event is reached
+/// that was already handled or that is older than the cutoff
+async fn paginate_to_last_handled_event(event_handler: &EventHandler, room: &Joined, prev_batch: Option<String>, cutoff: SystemTime) -> Vec<AnySyncMessageLikeEvent> {
+    let mut events = Vec::new();
+    let mut from = prev_batch;
+    let cutoff = MilliSecondsSinceUnixEpoch::from_system_time(cutoff).unwrap_or(MilliSecondsSinceUnixEpoch(UInt::MIN));
+
+    for _ in 0..MAX_PAGES_PER_ROOM {
+        let mut options = MessagesOptions::backward().from(from.as_deref());
+        options.limit = UInt::from(100u32);
+        let messages = match room.messages(options).await {
+            Ok(messages) => messages,
+            Err(e) => {
+                println!("Unable to load missed events of room {}: {}", room.room_id(), e); // error level
+                break;
+            }
+        };
+
+        // Backward pagination returns the newest events first
+        for timeline_event in &messages.chunk {
+            let event = match timeline_event.event.cast_ref::<AnySyncTimelineEvent>().deserialize() {
+                Ok(event) => event,
+                Err(_) => continue,
+            };
+            if event.origin_server_ts() < cutoff || find_event_in_db(event_handler.conn(), &event.event_id().to_string()).is_some() {
+                return events;
+            }
+            if let AnySyncTimelineEvent::MessageLike(message_like_event) = event {
+                events.push(message_like_event);
+            }
+        }
+
+        if messages.chunk.is_empty() || messages.end.is_none() {
+            break;
+        }
+        from = messages.end;
+    }
+
+    events
+}
+
+fn to_message_like_event(raw: &Raw<AnySyncTimelineEvent>) -> Option<AnySyncMessageLikeEvent> {
+    match raw.deserialize() {
+        Ok(AnySyncTimelineEvent::MessageLike(event)) => Some(event),
+        _ => None,
+    }
+}
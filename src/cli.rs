@@ -0,0 +1,165 @@
+use std::env;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use anyhow::{anyhow, bail};
+use rusqlite::Connection;
+use crate::data::{migrate_database, open_database};
+use crate::data::emoji::find_all_emoji_for_room_in_db;
+use crate::data::room_setting::find_room_settings_in_db;
+use crate::data::transaction::{change_social_credit, Transaction};
+use crate::data::user::{parse_user_type, update_user, UserType};
+use crate::utils::backup_util::backup_database;
+use crate::utils::export_util::{export_data, ExportFormat, import_data, ImportMode, read_export_data, write_export_data};
+use crate::utils::score_util::ScoreBounds;
+use crate::utils::user_util::setup_user;
+
+const USAGE: &str = "Usage: matrix-social-credits [command]
+
+Commands:
+    serve                                       Start the bot, this is the default without a command
+    db migrate                                  Migrate the database to the latest schema version
+    db backup <path>                            Write a consistent copy of the database to <path>
+    user set-role <user_id> <default|moderator|admin>
+                                                Change the role of a user, for example @alice:matrix.org
+    score set <room_id> <user_id> <social_credit>
+                                                Set the social credit of a user in a room
+    emoji list <room_id>                        List the registered emojis of a room
+    export <json|csv> <path> [room_id]          Export users, scores, emojis and reaction history of one room or all rooms,
+                                                json is written to the file <path>, csv to one file per table in the directory <path>
+    import <json|csv> <path> [--replace] [--dry-run]
+                                                Import an export, merges into the existing data by default,
+                                                --replace deletes the existing data of the imported rooms first
+                                                and also replaces the roles of existing users,
+                                                --dry-run only reports what would change
+
+All commands except serve only need DB_PATH and work without a matrix connection";
+
+/// Runs an offline administration command against the database at DB_PATH
+pub fn run(args: &[String]) -> anyhow::Result<()> {
+    let args: Vec<&str> = args.iter().map(|arg| arg.as_str()).collect();
+    match args.as_slice() {
+        ["db", "migrate"] => db_migrate(),
+        ["db", "backup", path] => db_backup(path),
+        ["user", "set-role", user_id, role] => user_set_role(user_id, role),
+        ["score", "set", room_id, user_id, social_credit] => score_set(room_id, user_id, social_credit),
+        ["emoji", "list", room_id] => emoji_list(room_id),
+        ["export", rest @ ..] => export(rest),
+        ["import", rest @ ..] => import(rest),
+        ["help"] | ["--help"] | ["-h"] => {
+            println!("{}", USAGE);
+            Ok(())
+        },
+        _ => bail!("Unknown command or invalid arguments: {}\n\n{}", args.join(" "), USAGE),
+    }
+}
+
+fn db_path() -> anyhow::Result<String> {
+    env::var("DB_PATH").map_err(|_| anyhow!("DB_PATH not set"))
+}
+
+fn open_shared_database() -> anyhow::Result<Arc<Mutex<Connection>>> {
+    Ok(Arc::new(Mutex::new(open_database(&db_path()?))))
+}
+
+fn db_migrate() -> anyhow::Result<()> {
+    let conn = Connection::open(db_path()?)?;
+    let (old_version, new_version) = migrate_database(&conn);
+    if old_version == new_version {
+        println!("Database is up to date, schema version {}", new_version);
+    }
+    else {
+        println!("Migrated database from schema version {} to {}", old_version, new_version);
+    }
+    Ok(())
+}
+
+fn db_backup(path: &str) -> anyhow::Result<()> {
+    let conn = open_database(&db_path()?);
+    backup_database(&conn, Path::new(path))?;
+    println!("Database backed up to {}", path);
+    Ok(())
+}
+
+fn user_set_role(user_id: &str, role: &str) -> anyhow::Result<()> {
+    let user_type = parse_user_type(role).ok_or(anyhow!("Invalid role {}, expected default, moderator or admin", role))?;
+    let conn = open_shared_database()?;
+    let mut user = setup_user(&conn, None, &user_id.to_string(), user_type.clone(), 0)
+        .ok_or(anyhow!("Invalid user id {}, expected for example @alice:matrix.org", user_id))?;
+
+    user.user_type = user_type;
+    update_user(&conn, &user)?;
+    println!("Role of {} set to {}", user.mxid, role);
+    Ok(())
+}
+
+fn score_set(room_id: &str, user_id: &str, social_credit: &str) -> anyhow::Result<()> {
+    let social_credit = social_credit.parse::<i32>().map_err(|_| anyhow!("Invalid social credit {}", social_credit))?;
+    let conn = open_shared_database()?;
+    let user = setup_user(&conn, Some(room_id), &user_id.to_string(), UserType::Default, social_credit)
+        .ok_or(anyhow!("Invalid user id {}, expected for example @alice:matrix.org", user_id))?;
+    let room_data = user.room_data.ok_or(anyhow!("Unable to set up room data for {} in {}", user_id, room_id))?;
+
+    let bounds = ScoreBounds::from_settings(&find_room_settings_in_db(&conn, room_id)).without_max_change();
+    let record = Transaction::new(room_data.id, None, "admin", 0, 0, Some(String::from("Set from the command line")));
+    let (old_social_credit, _) = change_social_credit(&conn, &record, &bounds, |old_social_credit| {
+        let delta = i32::try_from(social_credit as i64 - old_social_credit as i64).ok()?;
+        Some(delta).filter(|delta| !bounds.apply_change(old_social_credit, *delta).clamped)
+    })?.ok_or(anyhow!("The social credit of {} in {} has to stay between {} and {}", user.mxid, room_id, bounds.min, bounds.max))?;
+
+    println!("Social credit of {} in {} changed from {} to {}", user.mxid, room_id, old_social_credit, social_credit);
+    Ok(())
+}
+
+fn emoji_list(room_id: &str) -> anyhow::Result<()> {
+    let conn = open_shared_database()?;
+    let mut emojis = find_all_emoji_for_room_in_db(&conn, &room_id.to_string()).ok_or(anyhow!("Unable to read emojis"))?;
+    if emojis.is_empty() {
+        println!("No emojis registered in {}", room_id);
+        return Ok(());
+    }
+
+    emojis.sort_by_key(|emoji| -emoji.social_credit);
+    for emoji in emojis {
+        println!("{}\t{}", emoji.emoji, emoji.social_credit);
+    }
+    Ok(())
+}
+
+fn parse_format(format: Option<&&str>) -> anyhow::Result<ExportFormat> {
+    format.and_then(|format| ExportFormat::parse(format))
+        .ok_or(anyhow!("Missing or invalid format, expected json or csv\n\n{}", USAGE))
+}
+
+fn export(args: &[&str]) -> anyhow::Result<()> {
+    let format = parse_format(args.first())?;
+    let path = args.get(1).ok_or(anyhow!("Missing path\n\n{}", USAGE))?;
+    let room_id = args.get(2).map(|room_id| room_id.to_string());
+
+    let conn = open_database(&db_path()?);
+    let data = export_data(&conn, room_id.as_ref())?;
+    write_export_data(&data, format, Path::new(path)).map_err(|e| anyhow!("Export failed: {}", e))?;
+
+    println!(
+        "Exported {} users, {} user room data, {} emojis, {} reactions, {} transactions and {} budgets to {}",
+        data.users.len(), data.user_room_data.len(), data.emojis.len(), data.reactions.len(), data.transactions.len(), data.budgets.len(), path
+    );
+    Ok(())
+}
+
+fn import(args: &[&str]) -> anyhow::Result<()> {
+    let format = parse_format(args.first())?;
+    let path = args.get(1).ok_or(anyhow!("Missing path\n\n{}", USAGE))?;
+    let flags = &args[2.min(args.len())..];
+    if let Some(flag) = flags.iter().find(|flag| **flag != "--replace" && **flag != "--dry-run") {
+        bail!("Unknown option: {}\n\n{}", flag, USAGE);
+    }
+    let mode = if flags.contains(&"--replace") { ImportMode::Replace } else { ImportMode::Merge };
+    let dry_run = flags.contains(&"--dry-run");
+
+    let data = read_export_data(format, Path::new(path)).map_err(|e| anyhow!("Unable to read {}: {}", path, e))?;
+    let mut conn = open_database(&db_path()?);
+    let report = import_data(&mut conn, &data, mode, dry_run).map_err(|e| anyhow!("Import failed, nothing was changed: {}", e))?;
+
+    print!("{}", report);
+    Ok(())
+}
@@ -0,0 +1,90 @@
+use std::env;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+
+/// All settings of the bot, read from the environment variables on start
+#[derive(Clone)]
+pub struct Config {
+    pub db_path: String,
+    pub initial_social_credit: i32,
+    pub reaction_timespan: i32,
+    pub reaction_limit: i32,
+    pub admin_username: String,
+    pub username: String,
+    pub password: String,
+    pub homeserver_url: String,
+    pub dashboard_enabled: bool,
+    pub dashboard_address: SocketAddr,
+    pub backup: Option<BackupConfig>,
+    pub maintenance: MaintenanceConfig,
+}
+
+#[derive(Clone)]
+pub struct BackupConfig {
+    pub directory: PathBuf,
+    pub interval_hours: u64,
+    pub keep_daily: usize,
+    pub keep_weekly: usize,
+}
+
+#[derive(Clone)]
+pub struct MaintenanceConfig {
+    pub interval_hours: u64,
+    pub event_retention_days: u64,
+    pub reaction_retention_days: u64,
+}
+
+impl Config {
+    pub fn from_env() -> Self {
+        Config {
+            db_path: env::var("DB_PATH").expect("DB_PATH not set"),
+            initial_social_credit: get_env_var_as_i32("INITIAL_SOCIAL_CREDIT"),
+            reaction_timespan: get_env_var_as_i32("REACTION_TIMESPAN"),
+            reaction_limit: get_env_var_as_i32("REACTION_LIMIT"),
+            admin_username: env::var("ADMIN_USERNAME").expect("ADMIN_USERNAME not set"),
+            username: env::var("MATRIX_USERNAME").expect("MATRIX_USERNAME not set"),
+            password: env::var("MATRIX_PASSWORD").expect("MATRIX_PASSWORD not set"),
+            homeserver_url: env::var("MATRIX_HOMESERVER_URL").expect("MATRIX_HOMESERVER_URL not set"),
+            dashboard_enabled: env::var("DASHBOARD_ENABLED").map_or(true, |value| value != "false"),
+            dashboard_address: env::var("DASHBOARD_ADDRESS")
+                .unwrap_or(String::from("127.0.0.1:8080"))
+                .parse()
+                .expect("Failed to parse DASHBOARD_ADDRESS"),
+            backup: BackupConfig::from_env(),
+            maintenance: MaintenanceConfig {
+                interval_hours: get_env_var_or_default("MAINTENANCE_INTERVAL_HOURS", 24),
+                event_retention_days: get_env_var_or_default("EVENT_RETENTION_DAYS", 30),
+                reaction_retention_days: get_env_var_or_default("REACTION_RETENTION_DAYS", 365),
+            },
+        }
+    }
+}
+
+impl BackupConfig {
+    /// Backups are only enabled if BACKUP_DIR is set
+    pub fn from_env() -> Option<Self> {
+        let directory = env::var("BACKUP_DIR").ok().filter(|directory| !directory.is_empty())?;
+        Some(BackupConfig {
+            directory: PathBuf::from(directory),
+            interval_hours: get_env_var_or_default("BACKUP_INTERVAL_HOURS", 24),
+            keep_daily: get_env_var_or_default("BACKUP_KEEP_DAILY", 7),
+            keep_weekly: get_env_var_or_default("BACKUP_KEEP_WEEKLY", 4),
+        })
+    }
+}
+
+fn get_env_var_as_i32(var_name: &str) -> i32 {
+    env::var(var_name)
+        .map_err(|e| format!("Couldn't read {}: {}", var_name, e))
+        .and_then(|value| {
+            value.parse::<i32>().map_err(|e| format!("Failed to parse {}: {}", var_name, e))
+        })
+        .unwrap_or_else(|e| panic!("{}", e))
+}
+
+fn get_env_var_or_default<T: std::str::FromStr>(var_name: &str, default: T) -> T {
+    match env::var(var_name) {
+        Ok(value) => value.parse::<T>().unwrap_or_else(|_| panic!("Failed to parse {}", var_name)),
+        Err(_) => default,
+    }
+}
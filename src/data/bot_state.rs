@@ -0,0 +1,50 @@
+use std::sync::{Arc, Mutex};
+use rusqlite::{Connection, Error, OptionalExtension, params};
+
+/// Key value store for state of the bot itself that has to survive restarts
+pub fn create_table_bot_state(conn: &Connection) {
+    conn.execute("CREATE TABLE IF NOT EXISTS bot_state (
+            key TEXT PRIMARY KEY,
+            value TEXT NOT NULL
+    )", []).expect("Failed to create bot_state table");
+}
+
+pub fn get_bot_state(conn: &Arc<Mutex<Connection>>, key: &str) -> Option<String> {
+    let sql = "SELECT value FROM bot_state WHERE key=?1";
+    let connection = conn.lock().unwrap();
+
+    match connection.query_row(sql, params![key], |row| row.get(0)).optional() {
+        Ok(value) => value,
+        Err(e) => {
+            println!("Database error: {}", e);
+            None
+        },
+    }
+}
+
+pub fn set_bot_state(conn: &Arc<Mutex<Connection>>, key: &str, value: &str) -> Result<(), Error> {
+    let sql = "INSERT INTO bot_state (key, value) VALUES (?1, ?2) ON CONFLICT(key) DO UPDATE SET value=excluded.value";
+    let connection = conn.lock().unwrap();
+
+    connection.execute(sql, params![key, value])?;
+    Ok(())
+}
+
+pub fn delete_bot_state(conn: &Arc<Mutex<Connection>>, key: &str) -> Result<(), Error> {
+    let sql = "DELETE FROM bot_state WHERE key=?1";
+    let connection = conn.lock().unwrap();
+
+    connection.execute(sql, params![key])?;
+    Ok(())
+}
+
+const SYNC_TOKEN_KEY: &str = "sync_token";
+
+/// The next_batch token of the last sync response whose events were all handled
+pub fn get_sync_token(conn: &Arc<Mutex<Connection>>) -> Option<String> {
+    get_bot_state(conn, SYNC_TOKEN_KEY)
+}
+
+pub fn set_sync_token(conn: &Arc<Mutex<Connection>>, token: &str) -> Result<(), Error> {
+    set_bot_state(conn, SYNC_TOKEN_KEY, token)
+}
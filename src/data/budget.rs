@@ -0,0 +1,42 @@
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+use rusqlite::{Connection, Error, OptionalExtension, params};
+
+/// How much absolute social credit a user handed out in a room within the current budget period
+pub fn create_table_credit_budget(conn: &Connection) {
+    conn.execute("CREATE TABLE IF NOT EXISTS credit_budget (
+            user_room_data_id INTEGER PRIMARY KEY REFERENCES user_room_data(id),
+            period_start INTEGER NOT NULL,
+            spent INTEGER NOT NULL
+    )", []).expect("Failed to create credit_budget table");
+}
+
+/// Returns what the user spent in the period that started at period_start, spending of older periods does not count
+pub fn find_budget_spent_in_db(conn: &Arc<Mutex<Connection>>, user_room_data_id: i32, period_start: SystemTime) -> i64 {
+    let sql = "SELECT spent FROM credit_budget WHERE user_room_data_id=?1 AND period_start=?2";
+    let connection = conn.lock().unwrap();
+
+    match connection.query_row(sql, params![user_room_data_id, to_epoch_secs(period_start)], |row| row.get(0)).optional() {
+        Ok(spent) => spent.unwrap_or(0),
+        Err(e) => {
+            println!("Database error: {}", e);
+            0
+        },
+    }
+}
+
+/// Adds the amount to the spending of the period, the spending of an older period is replaced
+pub fn add_budget_spending(conn: &Arc<Mutex<Connection>>, user_room_data_id: i32, period_start: SystemTime, amount: i64) -> Result<(), Error> {
+    let sql = "INSERT INTO credit_budget (user_room_data_id, period_start, spent) VALUES (?1, ?2, ?3) \
+                        ON CONFLICT(user_room_data_id) DO UPDATE SET \
+                        spent=CASE WHEN period_start=excluded.period_start THEN spent+excluded.spent ELSE excluded.spent END, \
+                        period_start=excluded.period_start";
+    let connection = conn.lock().unwrap();
+
+    connection.execute(sql, params![user_room_data_id, to_epoch_secs(period_start), amount])?;
+    Ok(())
+}
+
+fn to_epoch_secs(time: SystemTime) -> i64 {
+    time.duration_since(SystemTime::UNIX_EPOCH).unwrap_or(Duration::from_secs(0)).as_secs() as i64
+}
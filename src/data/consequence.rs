@@ -0,0 +1,259 @@
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+use rusqlite::{Connection, Error, OptionalExtension, params, Params};
+
+/// A rule of a room that takes an action against or for a user whose social credit is below or above the threshold
+#[derive(Clone)]
+pub struct Consequence {
+    pub id: i32,
+    pub room_id: String,
+    pub below: bool, // true for scores below the threshold, false for scores above it
+    pub threshold: i32,
+    pub action: String, // power_level, mute or kick
+    pub power_level: Option<i64>, // The power level that is set by the power_level action
+    pub duration_minutes: Option<i64>, // None keeps the consequence until the score recovers
+}
+
+impl Consequence {
+    pub fn applies_to(&self, social_credit: i32) -> bool {
+        if self.below {
+            social_credit < self.threshold
+        }
+        else {
+            social_credit > self.threshold
+        }
+    }
+}
+
+/// A consequence that was applied to a user, it is kept until the score of the user recovers
+/// so the consequence is not applied again while the score stays past the threshold
+#[derive(Clone)]
+pub struct AppliedConsequence {
+    pub id: i32,
+    pub consequence_id: i32,
+    pub room_id: String,
+    pub mxid: String,
+    pub previous_power_level: Option<i64>, // The power level that is restored when the consequence is reverted
+    pub expires_at: Option<SystemTime>,
+    pub active: bool, // false once the consequence was reverted or if it can not be reverted, like a kick
+}
+
+pub fn create_table_consequence(conn: &Connection) {
+    conn.execute("CREATE TABLE IF NOT EXISTS consequence (
+            id INTEGER PRIMARY KEY,
+            room_id TEXT NOT NULL,
+            below INTEGER NOT NULL,
+            threshold INTEGER NOT NULL,
+            action TEXT NOT NULL,
+            power_level INTEGER,
+            duration_minutes INTEGER
+    )", []).expect("Failed to create consequence table");
+}
+
+pub fn create_table_applied_consequence(conn: &Connection) {
+    conn.execute("CREATE TABLE IF NOT EXISTS applied_consequence (
+            id INTEGER PRIMARY KEY,
+            consequence_id INTEGER NOT NULL REFERENCES consequence(id) ON DELETE CASCADE,
+            room_id TEXT NOT NULL,
+            mxid TEXT NOT NULL,
+            previous_power_level INTEGER,
+            applied_at INTEGER NOT NULL,
+            expires_at INTEGER,
+            active INTEGER NOT NULL,
+            UNIQUE (consequence_id, mxid)
+    )", []).expect("Failed to create applied_consequence table");
+}
+
+pub fn insert_consequence(conn: &Arc<Mutex<Connection>>, consequence: &Consequence) -> Result<i64, Error> {
+    let sql = "INSERT INTO consequence (room_id, below, threshold, action, power_level, duration_minutes) VALUES (?1, ?2, ?3, ?4, ?5, ?6)";
+    let connection = conn.lock().unwrap();
+
+    connection.execute(
+        sql,
+        params![
+            &consequence.room_id,
+            &consequence.below,
+            &consequence.threshold,
+            &consequence.action,
+            &consequence.power_level,
+            &consequence.duration_minutes,
+        ]
+    )?;
+
+    Ok(connection.last_insert_rowid())
+}
+
+/// Returns the number of deleted consequences, applied consequences of the rule are deleted with it
+pub fn delete_consequence(conn: &Arc<Mutex<Connection>>, room_id: &str, id: i32) -> Result<usize, Error> {
+    let sql = "DELETE FROM consequence WHERE room_id=?1 AND id=?2";
+    let connection = conn.lock().unwrap();
+
+    connection.execute(sql, params![room_id, id])
+}
+
+pub fn find_consequence_in_db(conn: &Arc<Mutex<Connection>>, room_id: &str, id: i32) -> Option<Consequence> {
+    let sql = "SELECT * FROM consequence WHERE room_id=?1 AND id=?2";
+    let params = params![room_id, id];
+    match do_get_consequence_sql(conn, sql, params) {
+        Ok(mut consequences) => consequences.pop(),
+        Err(e) => {
+            println!("Database error: {}", e);
+            None
+        },
+    }
+}
+
+pub fn find_all_consequences_for_room_in_db(conn: &Arc<Mutex<Connection>>, room_id: &str) -> Option<Vec<Consequence>> {
+    let sql = "SELECT * FROM consequence WHERE room_id=?1 ORDER BY id ASC";
+    let params = params![room_id];
+    match do_get_consequence_sql(conn, sql, params) {
+        Ok(consequences) => Some(consequences),
+        Err(e) => {
+            println!("Database error: {}", e);
+            None
+        },
+    }
+}
+
+pub fn find_all_room_ids_with_consequences_in_db(conn: &Arc<Mutex<Connection>>) -> Result<Vec<String>, Error> {
+    let sql = "SELECT DISTINCT room_id FROM consequence";
+    let connection = conn.lock().unwrap();
+    let mut stmt = connection.prepare(sql)?;
+
+    let room_ids: Result<Vec<String>, _> = stmt.query_map([], |row| row.get(0))
+        .and_then(|mapped_rows| mapped_rows.collect());
+    room_ids
+}
+
+fn do_get_consequence_sql<P: Params>(
+    conn: &Arc<Mutex<Connection>>,
+    sql: &str,
+    params: P,
+) -> Result<Vec<Consequence>, Error> {
+    let connection = conn.lock().unwrap();
+    let mut stmt = connection.prepare(sql)?;
+
+    let consequences: Result<Vec<Consequence>, _> = stmt.query_map(params, |row| {
+        Ok(Consequence {
+            id: row.get(0)?,
+            room_id: row.get(1)?,
+            below: row.get(2)?,
+            threshold: row.get(3)?,
+            action: row.get(4)?,
+            power_level: row.get(5)?,
+            duration_minutes: row.get(6)?,
+        })
+    }).and_then(|mapped_rows| mapped_rows.collect());
+
+    consequences
+}
+
+pub fn insert_applied_consequence(conn: &Arc<Mutex<Connection>>, applied: &AppliedConsequence) -> Result<(), Error> {
+    let sql = "INSERT INTO applied_consequence (consequence_id, room_id, mxid, previous_power_level, applied_at, expires_at, active) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)";
+    let connection = conn.lock().unwrap();
+
+    connection.execute(
+        sql,
+        params![
+            &applied.consequence_id,
+            &applied.room_id,
+            &applied.mxid,
+            &applied.previous_power_level,
+            &to_epoch_secs(SystemTime::now()),
+            &applied.expires_at.map(to_epoch_secs),
+            &applied.active,
+        ]
+    )?;
+
+    Ok(())
+}
+
+/// Marks the applied consequence as reverted, it stays stored until the score of the user recovers
+pub fn deactivate_applied_consequence(conn: &Arc<Mutex<Connection>>, id: i32) -> Result<(), Error> {
+    let sql = "UPDATE applied_consequence SET active=0 WHERE id=?1";
+    let connection = conn.lock().unwrap();
+
+    connection.execute(sql, params![id])?;
+    Ok(())
+}
+
+pub fn delete_applied_consequence(conn: &Arc<Mutex<Connection>>, id: i32) -> Result<(), Error> {
+    let sql = "DELETE FROM applied_consequence WHERE id=?1";
+    let connection = conn.lock().unwrap();
+
+    connection.execute(sql, params![id])?;
+    Ok(())
+}
+
+pub fn find_applied_consequence_in_db(conn: &Arc<Mutex<Connection>>, consequence_id: i32, mxid: &str) -> Option<AppliedConsequence> {
+    let sql = "SELECT * FROM applied_consequence WHERE consequence_id=?1 AND mxid=?2";
+    let connection = conn.lock().unwrap();
+
+    match connection.query_row(sql, params![consequence_id, mxid], map_applied_consequence).optional() {
+        Ok(applied) => applied,
+        Err(e) => {
+            println!("Database error: {}", e);
+            None
+        },
+    }
+}
+
+pub fn find_applied_consequences_for_consequence_in_db(conn: &Arc<Mutex<Connection>>, consequence_id: i32) -> Option<Vec<AppliedConsequence>> {
+    let sql = "SELECT * FROM applied_consequence WHERE consequence_id=?1";
+    let params = params![consequence_id];
+    match do_get_applied_consequence_sql(conn, sql, params) {
+        Ok(applied) => Some(applied),
+        Err(e) => {
+            println!("Database error: {}", e);
+            None
+        },
+    }
+}
+
+/// Returns the active consequences whose duration ended before the given time
+pub fn find_expired_applied_consequences_in_db(conn: &Arc<Mutex<Connection>>, time: SystemTime) -> Option<Vec<AppliedConsequence>> {
+    let sql = "SELECT * FROM applied_consequence WHERE active=1 AND expires_at IS NOT NULL AND expires_at<=?1";
+    let params = params![to_epoch_secs(time)];
+    match do_get_applied_consequence_sql(conn, sql, params) {
+        Ok(applied) => Some(applied),
+        Err(e) => {
+            println!("Database error: {}", e);
+            None
+        },
+    }
+}
+
+fn do_get_applied_consequence_sql<P: Params>(
+    conn: &Arc<Mutex<Connection>>,
+    sql: &str,
+    params: P,
+) -> Result<Vec<AppliedConsequence>, Error> {
+    let connection = conn.lock().unwrap();
+    let mut stmt = connection.prepare(sql)?;
+
+    let applied: Result<Vec<AppliedConsequence>, _> = stmt.query_map(params, map_applied_consequence)
+        .and_then(|mapped_rows| mapped_rows.collect());
+
+    applied
+}
+
+fn map_applied_consequence(row: &rusqlite::Row) -> Result<AppliedConsequence, Error> {
+    let expires_at: Option<i64> = row.get(6)?;
+    Ok(AppliedConsequence {
+        id: row.get(0)?,
+        consequence_id: row.get(1)?,
+        room_id: row.get(2)?,
+        mxid: row.get(3)?,
+        previous_power_level: row.get(4)?,
+        expires_at: expires_at.map(from_epoch_secs),
+        active: row.get(7)?,
+    })
+}
+
+fn to_epoch_secs(time: SystemTime) -> i64 {
+    time.duration_since(SystemTime::UNIX_EPOCH).unwrap_or(Duration::from_secs(0)).as_secs() as i64
+}
+
+fn from_epoch_secs(secs: i64) -> SystemTime {
+    SystemTime::UNIX_EPOCH + Duration::from_secs(secs.max(0) as u64)
+}
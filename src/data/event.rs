@@ -1,4 +1,5 @@
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
 use rusqlite::{Connection, Error, params, Params, ToSql};
 
 #[derive(Clone)]
@@ -6,6 +7,7 @@ pub struct Event {
     pub id: String,
     pub event_type: String,
     pub handled: bool,
+    pub time: SystemTime, // When the event was handled
 }
 
 pub fn create_table_event(conn: &Connection) {
@@ -16,8 +18,21 @@ pub fn create_table_event(conn: &Connection) {
     )", []).expect("Failed to create event table");
 }
 
+/// Events handled before this column existed get the time of the migration
+pub fn migrate_table_event_add_time(conn: &Connection) {
+    conn.execute("ALTER TABLE event ADD COLUMN time INTEGER NOT NULL DEFAULT 0", []).expect("Failed to add time to event table");
+    conn.execute("UPDATE event SET time = strftime('%s', 'now')", []).expect("Failed to set time of handled events");
+}
+
+/// Deletes all handled events that are older than the given epoch time, returns the number of deleted events
+pub fn cleanup_table_event(conn: &Connection, epoch_time: i64) -> Result<usize, Error> {
+    let sql = "DELETE FROM event WHERE time < ?1";
+    conn.execute(sql, [epoch_time])
+}
+
 pub fn insert_event(conn: &Arc<Mutex<Connection>>, event: &Event) -> Result<(), Error> {
-    let sql = "INSERT INTO event (id, event_type, handled) VALUES (?1, ?2, ?3)";
+    let sql = "INSERT INTO event (id, event_type, handled, time) VALUES (?1, ?2, ?3, ?4)";
+    let epoch_secs = event.time.duration_since(SystemTime::UNIX_EPOCH).unwrap_or(Duration::from_secs(0)).as_secs() as i64;
 
     let connection = conn.lock().unwrap();
 
@@ -26,7 +41,8 @@ pub fn insert_event(conn: &Arc<Mutex<Connection>>, event: &Event) -> Result<(),
         &[
             &event.id as &dyn ToSql,
             &event.event_type as &dyn ToSql,
-            &event.handled as &dyn ToSql
+            &event.handled as &dyn ToSql,
+            &epoch_secs as &dyn ToSql,
         ]
     )?;
 
@@ -37,7 +53,7 @@ pub fn find_event_in_db(
     conn: &Arc<Mutex<Connection>>,
     id: &String
 ) -> Option<Event> {
-    let sql = "SELECT * FROM event WHERE id=?1";
+    let sql = "SELECT id, event_type, handled, time FROM event WHERE id=?1";
     let params = params![id];
     match do_get_event_sql(conn, sql, params) {
         Ok(mut users) => {
@@ -72,6 +88,7 @@ fn do_get_event_sql<P: Params>(
             id: row.get(0)?,
             event_type: row.get(1)?,
             handled: row.get(2)?,
+            time: SystemTime::UNIX_EPOCH + Duration::from_secs(row.get::<_, i64>(3)?.max(0) as u64),
         })
     }).and_then(|mapped_rows| mapped_rows.collect());
 
@@ -1,5 +1,90 @@
+use std::time::Duration;
+use rusqlite::Connection;
+use crate::data::consequence::{create_table_applied_consequence, create_table_consequence};
+use crate::data::emoji::create_table_emoji;
+use crate::data::bot_state::create_table_bot_state;
+use crate::data::budget::create_table_credit_budget;
+use crate::data::event::{create_table_event, migrate_table_event_add_time};
+use crate::data::rank::create_table_rank;
+use crate::data::room_setting::create_table_room_setting;
+use crate::data::season::{create_table_season, create_table_season_standing};
+use crate::data::transaction::{create_table_transaction, migrate_table_transaction_add_emoji_social_credit};
+use crate::data::user::{create_table_user, migrate_table_user_add_mxid};
+use crate::data::user_reaction::{create_index_user_reaction_message, create_table_user_reaction};
+use crate::data::user_room_data::create_table_user_room_data;
+
 pub mod user;
 pub mod emoji;
 pub mod event;
 pub mod user_room_data;
 pub(crate) mod user_reaction;
+pub mod transaction;
+pub mod bot_state;
+pub mod room_setting;
+pub mod rank;
+pub mod consequence;
+pub mod budget;
+pub mod season;
+
+/// Every migration brings the schema from the version of its index to the next version,
+/// the current version is stored in the user_version pragma of the database
+const MIGRATIONS: &[fn(&Connection)] = &[
+    migration_create_tables,
+    migration_event_time_and_bot_state,
+    create_table_room_setting,
+    migrate_table_user_add_mxid,
+    create_table_rank,
+    migration_consequences,
+    create_table_credit_budget,
+    migration_seasons,
+    migrate_table_transaction_add_emoji_social_credit,
+    create_index_user_reaction_message,
+];
+
+/// How long a connection waits for a lock that another connection holds, like the backup or VACUUM of the maintenance
+pub const BUSY_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Opens the database and migrates it to the latest schema version
+pub fn open_database(db_path: &str) -> Connection {
+    let conn = Connection::open(db_path).expect("Failed to open database");
+    conn.busy_timeout(BUSY_TIMEOUT).expect("Failed to set the busy timeout");
+    conn.execute("PRAGMA foreign_keys = ON", []).expect("Failed to enable foreign key support");
+    migrate_database(&conn);
+    conn
+}
+
+/// Runs all pending migrations, returns the schema version before and after
+pub fn migrate_database(conn: &Connection) -> (usize, usize) {
+    let version: usize = conn.query_row("PRAGMA user_version", [], |row| row.get(0)).expect("Failed to read schema version");
+    for (index, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+        println!("Migrating database to version {}", index + 1);
+        migration(conn);
+        conn.pragma_update(None, "user_version", index + 1).expect("Failed to update schema version");
+    }
+    (version, MIGRATIONS.len().max(version))
+}
+
+/// Also runs for databases from before versioning, so all tables are only created if they do not exist
+fn migration_create_tables(conn: &Connection) {
+    create_table_user(conn);
+    create_table_user_room_data(conn);
+    create_table_user_reaction(conn);
+    create_table_emoji(conn);
+    create_table_event(conn);
+    create_table_transaction(conn);
+}
+
+fn migration_event_time_and_bot_state(conn: &Connection) {
+    migrate_table_event_add_time(conn);
+    create_table_bot_state(conn);
+}
+
+fn migration_consequences(conn: &Connection) {
+    create_table_consequence(conn);
+    create_table_applied_consequence(conn);
+}
+
+fn migration_seasons(conn: &Connection) {
+    create_table_season(conn);
+    create_table_season_standing(conn);
+}
@@ -0,0 +1,90 @@
+use std::sync::{Arc, Mutex};
+use rusqlite::{Connection, Error, params, Params};
+
+/// A title for all users of a room whose social credit is within the range,
+/// min_social_credit is inclusive and max_social_credit exclusive, None means unbounded
+#[derive(Clone, PartialEq)]
+pub struct Rank {
+    pub id: i32,
+    pub room_id: String,
+    pub title: String,
+    pub min_social_credit: Option<i32>,
+    pub max_social_credit: Option<i32>,
+}
+
+impl Rank {
+    pub fn contains(&self, social_credit: i32) -> bool {
+        !matches!(self.min_social_credit, Some(min) if social_credit < min)
+            && !matches!(self.max_social_credit, Some(max) if social_credit >= max)
+    }
+}
+
+pub fn create_table_rank(conn: &Connection) {
+    conn.execute("CREATE TABLE IF NOT EXISTS rank (
+            id INTEGER PRIMARY KEY,
+            room_id TEXT NOT NULL,
+            title TEXT NOT NULL,
+            min_social_credit INTEGER,
+            max_social_credit INTEGER
+    )", []).expect("Failed to create rank table");
+}
+
+pub fn insert_rank(conn: &Arc<Mutex<Connection>>, rank: &Rank) -> Result<(), Error> {
+    let sql = "INSERT INTO rank (room_id, title, min_social_credit, max_social_credit) VALUES (?1, ?2, ?3, ?4)";
+    let connection = conn.lock().unwrap();
+
+    connection.execute(
+        sql,
+        params![
+            &rank.room_id,
+            &rank.title,
+            &rank.min_social_credit,
+            &rank.max_social_credit,
+        ]
+    )?;
+
+    Ok(())
+}
+
+/// Returns the number of deleted ranks
+pub fn delete_rank(conn: &Arc<Mutex<Connection>>, room_id: &str, title: &str) -> Result<usize, Error> {
+    let sql = "DELETE FROM rank WHERE room_id=?1 AND title=?2";
+    let connection = conn.lock().unwrap();
+
+    connection.execute(sql, params![room_id, title])
+}
+
+/// Returns the ranks of the room, the highest first
+pub fn find_all_ranks_for_room_in_db(conn: &Arc<Mutex<Connection>>, room_id: &str) -> Option<Vec<Rank>> {
+    let sql = "SELECT id, room_id, title, min_social_credit, max_social_credit FROM rank WHERE room_id=?1 \
+                        ORDER BY min_social_credit IS NULL, min_social_credit DESC, max_social_credit IS NULL DESC, max_social_credit DESC";
+    let params = params![room_id];
+    match do_get_rank_sql(conn, sql, params) {
+        Ok(ranks) => Some(ranks),
+        Err(e) => {
+            println!("Database error: {}", e);
+            None
+        },
+    }
+}
+
+fn do_get_rank_sql<P: Params>(
+    conn: &Arc<Mutex<Connection>>,
+    sql: &str,
+    params: P,
+) -> Result<Vec<Rank>, Error> {
+    let connection = conn.lock().unwrap();
+    let mut stmt = connection.prepare(sql)?;
+
+    let ranks: Result<Vec<Rank>, _> = stmt.query_map(params, |row| {
+        Ok(Rank {
+            id: row.get(0)?,
+            room_id: row.get(1)?,
+            title: row.get(2)?,
+            min_social_credit: row.get(3)?,
+            max_social_credit: row.get(4)?,
+        })
+    }).and_then(|mapped_rows| mapped_rows.collect());
+
+    ranks
+}
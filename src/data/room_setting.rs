@@ -0,0 +1,45 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use rusqlite::{Connection, Error, params};
+
+/// Settings that were changed for a single room, every setting that is not stored here uses its default
+pub fn create_table_room_setting(conn: &Connection) {
+    conn.execute("CREATE TABLE IF NOT EXISTS room_setting (
+            room_id TEXT NOT NULL,
+            key TEXT NOT NULL,
+            value TEXT NOT NULL,
+            PRIMARY KEY (room_id, key)
+    )", []).expect("Failed to create room_setting table");
+}
+
+pub fn find_room_settings_in_db(conn: &Arc<Mutex<Connection>>, room_id: &str) -> HashMap<String, String> {
+    let sql = "SELECT key, value FROM room_setting WHERE room_id=?1";
+    let connection = conn.lock().unwrap();
+
+    let settings: Result<HashMap<String, String>, Error> = connection.prepare(sql)
+        .and_then(|mut stmt| {
+            stmt.query_map(params![room_id], |row| Ok((row.get(0)?, row.get(1)?)))
+                .and_then(|mapped_rows| mapped_rows.collect())
+        });
+    settings.unwrap_or_else(|e| {
+        println!("Database error: {}", e);
+        HashMap::new()
+    })
+}
+
+pub fn set_room_setting(conn: &Arc<Mutex<Connection>>, room_id: &str, key: &str, value: &str) -> Result<(), Error> {
+    let sql = "INSERT INTO room_setting (room_id, key, value) VALUES (?1, ?2, ?3) ON CONFLICT(room_id, key) DO UPDATE SET value=excluded.value";
+    let connection = conn.lock().unwrap();
+
+    connection.execute(sql, params![room_id, key, value])?;
+    Ok(())
+}
+
+/// Removes the setting so the default is used again
+pub fn delete_room_setting(conn: &Arc<Mutex<Connection>>, room_id: &str, key: &str) -> Result<(), Error> {
+    let sql = "DELETE FROM room_setting WHERE room_id=?1 AND key=?2";
+    let connection = conn.lock().unwrap();
+
+    connection.execute(sql, params![room_id, key])?;
+    Ok(())
+}
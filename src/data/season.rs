@@ -0,0 +1,127 @@
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+use rusqlite::{Connection, Error, params, Params};
+
+/// A finished season of a room, the standings at its end are stored in season_standing
+pub struct Season {
+    pub id: i32,
+    pub number: i32,
+    pub ended_at: SystemTime,
+}
+
+pub struct SeasonStanding {
+    pub mxid: String,
+    pub social_credit: i32,
+}
+
+pub fn create_table_season(conn: &Connection) {
+    conn.execute("CREATE TABLE IF NOT EXISTS season (
+            id INTEGER PRIMARY KEY,
+            room_id TEXT NOT NULL,
+            number INTEGER NOT NULL,
+            ended_at INTEGER NOT NULL,
+            UNIQUE (room_id, number)
+    )", []).expect("Failed to create season table");
+}
+
+pub fn create_table_season_standing(conn: &Connection) {
+    conn.execute("CREATE TABLE IF NOT EXISTS season_standing (
+            season_id INTEGER NOT NULL REFERENCES season(id),
+            user_id INTEGER NOT NULL REFERENCES user(id),
+            social_credit INTEGER NOT NULL,
+            PRIMARY KEY (season_id, user_id)
+    )", []).expect("Failed to create season_standing table");
+}
+
+/// Archives the scores of all users of the room except the bot as a new season and resets them to initial_social_credit,
+/// every reset is recorded as a season transaction. Everything happens in a single database transaction.
+/// Returns the number of the season that ended
+pub fn end_season(conn: &Arc<Mutex<Connection>>, room_id: &str, bot_user_id: &str, initial_social_credit: i32) -> Result<i32, Error> {
+    let mut connection = conn.lock().unwrap();
+    let db_transaction = connection.transaction()?;
+    let epoch_secs = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap_or(Duration::from_secs(0)).as_secs() as i64;
+
+    let number: i32 = db_transaction.query_row("SELECT COALESCE(MAX(number), 0) + 1 FROM season WHERE room_id=?1", params![room_id], |row| row.get(0))?;
+    db_transaction.execute("INSERT INTO season (room_id, number, ended_at) VALUES (?1, ?2, ?3)", params![room_id, number, epoch_secs])?;
+    let season_id = db_transaction.last_insert_rowid();
+
+    let room_data_sql = "FROM user_room_data INNER JOIN user ON user.id=user_room_data.user_id WHERE user_room_data.room_id=?1 AND user.mxid!=?2";
+    db_transaction.execute(
+        &format!("INSERT INTO season_standing (season_id, user_id, social_credit) SELECT ?3, user_room_data.user_id, user_room_data.social_credit {}", room_data_sql),
+        params![room_id, bot_user_id, season_id],
+    )?;
+    db_transaction.execute(
+        &format!("INSERT INTO credit_transaction (user_room_data_id, sender_user_id, kind, delta, social_credit, reason, time) \
+                  SELECT user_room_data.id, NULL, 'season', ?3 - user_room_data.social_credit, ?3, ?4, ?5 {} AND user_room_data.social_credit!=?3", room_data_sql),
+        params![room_id, bot_user_id, initial_social_credit, format!("End of season {}", number), epoch_secs],
+    )?;
+    db_transaction.execute(
+        "UPDATE user_room_data SET social_credit=?2 WHERE room_id=?1 AND user_id NOT IN (SELECT id FROM user WHERE mxid=?3)",
+        params![room_id, initial_social_credit, bot_user_id],
+    )?;
+    db_transaction.commit()?;
+
+    Ok(number)
+}
+
+/// Returns the seasons of the room, the first season first
+pub fn find_all_seasons_for_room_in_db(conn: &Arc<Mutex<Connection>>, room_id: &str) -> Option<Vec<Season>> {
+    let sql = "SELECT id, number, ended_at FROM season WHERE room_id=?1 ORDER BY number ASC";
+    match do_get_season_sql(conn, sql, params![room_id]) {
+        Ok(seasons) => Some(seasons),
+        Err(e) => {
+            println!("Database error: {}", e);
+            None
+        },
+    }
+}
+
+pub fn find_season_in_db(conn: &Arc<Mutex<Connection>>, room_id: &str, number: i32) -> Option<Season> {
+    let sql = "SELECT id, number, ended_at FROM season WHERE room_id=?1 AND number=?2";
+    match do_get_season_sql(conn, sql, params![room_id, number]) {
+        Ok(mut seasons) => seasons.pop(),
+        Err(e) => {
+            println!("Database error: {}", e);
+            None
+        },
+    }
+}
+
+fn do_get_season_sql<P: Params>(
+    conn: &Arc<Mutex<Connection>>,
+    sql: &str,
+    params: P,
+) -> Result<Vec<Season>, Error> {
+    let connection = conn.lock().unwrap();
+    let mut stmt = connection.prepare(sql)?;
+
+    let seasons: Result<Vec<Season>, _> = stmt.query_map(params, |row| {
+        Ok(Season {
+            id: row.get(0)?,
+            number: row.get(1)?,
+            ended_at: SystemTime::UNIX_EPOCH + Duration::from_secs(row.get::<_, i64>(2)?.max(0) as u64),
+        })
+    }).and_then(|mapped_rows| mapped_rows.collect());
+
+    seasons
+}
+
+/// Returns the final standings of the season, the highest score first
+pub fn find_season_standings_in_db(conn: &Arc<Mutex<Connection>>, season_id: i32) -> Option<Vec<SeasonStanding>> {
+    let sql = "SELECT user.mxid, season_standing.social_credit FROM season_standing INNER JOIN user ON user.id=season_standing.user_id \
+                        WHERE season_standing.season_id=?1 ORDER BY season_standing.social_credit DESC, user.mxid ASC";
+    let connection = conn.lock().unwrap();
+
+    let standings: Result<Vec<SeasonStanding>, Error> = connection.prepare(sql)
+        .and_then(|mut stmt| {
+            stmt.query_map(params![season_id], |row| Ok(SeasonStanding { mxid: row.get(0)?, social_credit: row.get(1)? }))
+                .and_then(|mapped_rows| mapped_rows.collect())
+        });
+    match standings {
+        Ok(standings) => Some(standings),
+        Err(e) => {
+            println!("Database error: {}", e);
+            None
+        },
+    }
+}
@@ -0,0 +1,258 @@
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+use rusqlite::{Connection, Error, params, Params};
+use crate::utils::score_util::{ScoreBounds, ScoreChange};
+
+/// A single change of a users social credit in a room, used for the score history
+#[derive(Clone)]
+pub struct Transaction {
+    pub id: i32,
+    pub user_room_data_id: i32,
+    pub sender_user_id: Option<i32>, // None for changes made by the system itself
+    pub kind: String,
+    pub delta: i32,
+    pub social_credit: i32, // The social credit after the change was applied
+    pub reason: Option<String>,
+    pub time: SystemTime,
+    /// The social credit of the emoji for reactions, the sign of delta is lost once the bounds or the weights turn it into 0
+    pub emoji_social_credit: Option<i32>,
+}
+
+impl Transaction {
+    pub fn new(user_room_data_id: i32, sender_user_id: Option<i32>, kind: &str, delta: i32, social_credit: i32, reason: Option<String>) -> Self {
+        Self {
+            id: -1,
+            user_room_data_id,
+            sender_user_id,
+            kind: kind.to_string(),
+            delta,
+            social_credit,
+            reason,
+            time: SystemTime::now(),
+            emoji_social_credit: None,
+        }
+    }
+}
+
+pub fn create_table_transaction(conn: &Connection) {
+    conn.execute("CREATE TABLE IF NOT EXISTS credit_transaction (
+            id INTEGER PRIMARY KEY,
+            user_room_data_id INTEGER NOT NULL REFERENCES user_room_data(id),
+            sender_user_id INTEGER REFERENCES user(id),
+            kind TEXT NOT NULL,
+            delta INTEGER NOT NULL,
+            social_credit INTEGER NOT NULL,
+            reason TEXT,
+            time INTEGER NOT NULL
+    )", []).expect("Failed to create credit_transaction table");
+}
+
+pub fn migrate_table_transaction_add_emoji_social_credit(conn: &Connection) {
+    conn.execute("ALTER TABLE credit_transaction ADD COLUMN emoji_social_credit INTEGER", []).expect("Failed to add emoji_social_credit to credit_transaction table");
+}
+
+/// Returns the history of a user in a room, oldest first
+pub fn find_transactions_for_user_room_data(conn: &Arc<Mutex<Connection>>, user_room_data_id: i32) -> Option<Vec<Transaction>> {
+    let sql = "SELECT * FROM credit_transaction WHERE user_room_data_id=?1 ORDER BY time ASC, id ASC";
+    let params = params![user_room_data_id];
+    match do_get_transaction_sql(conn, sql, params) {
+        Ok(transactions) => Some(transactions),
+        Err(e) => {
+            println!("Database error: {}", e);
+            None
+        },
+    }
+}
+
+/// Returns the reactions a user applied to others in a room since the given time, oldest first
+pub fn find_reaction_transactions_by_sender_in_db(conn: &Arc<Mutex<Connection>>, sender_user_id: i32, room_id: &str, since: SystemTime) -> Option<Vec<Transaction>> {
+    find_transactions_by_sender_in_db(conn, sender_user_id, room_id, "reaction", since)
+}
+
+/// Returns the changes of the kind a user caused in a room since the given time, oldest first
+pub fn find_transactions_by_sender_in_db(conn: &Arc<Mutex<Connection>>, sender_user_id: i32, room_id: &str, kind: &str, since: SystemTime) -> Option<Vec<Transaction>> {
+    let sql = "SELECT credit_transaction.* FROM credit_transaction INNER JOIN user_room_data ON credit_transaction.user_room_data_id=user_room_data.id \
+                        WHERE credit_transaction.sender_user_id=?1 AND user_room_data.room_id=?2 AND credit_transaction.kind=?3 AND credit_transaction.time>=?4 \
+                        ORDER BY credit_transaction.time ASC, credit_transaction.id ASC";
+    let since_secs = since.duration_since(SystemTime::UNIX_EPOCH).unwrap_or(Duration::from_secs(0)).as_secs() as i64;
+    let params = params![sender_user_id, room_id, kind, since_secs];
+    match do_get_transaction_sql(conn, sql, params) {
+        Ok(transactions) => Some(transactions),
+        Err(e) => {
+            println!("Database error: {}", e);
+            None
+        },
+    }
+}
+
+/// Changes the social credit of a user_room_data and records the change in a single database transaction,
+/// the change is limited with ScoreBounds::apply_change so every score stays within the bounds of its room.
+/// change gets the current social credit and returns the requested delta, or None to leave the score unchanged.
+/// record is stored as history entry, its delta and social_credit are replaced with the applied change.
+/// Returns the old social credit and the applied change, or None if change refused
+pub fn change_social_credit(
+    conn: &Arc<Mutex<Connection>>,
+    record: &Transaction,
+    bounds: &ScoreBounds,
+    change: impl FnOnce(i32) -> Option<i32>,
+) -> Result<Option<(i32, ScoreChange)>, Error> {
+    let mut connection = conn.lock().unwrap();
+    let db_transaction = connection.transaction()?;
+    let old_social_credit: i32 = db_transaction.query_row("SELECT social_credit FROM user_room_data WHERE id=?1", params![record.user_room_data_id], |row| row.get(0))?;
+    let score_change = match change(old_social_credit) {
+        Some(delta) => bounds.apply_change(old_social_credit, delta),
+        None => return Ok(None),
+    };
+
+    let epoch_secs = record.time.duration_since(SystemTime::UNIX_EPOCH).unwrap_or(Duration::from_secs(0)).as_secs() as i64;
+    db_transaction.execute("UPDATE user_room_data SET social_credit=?1 WHERE id=?2", params![score_change.new_social_credit, record.user_room_data_id])?;
+    db_transaction.execute(
+        "INSERT INTO credit_transaction (user_room_data_id, sender_user_id, kind, delta, social_credit, reason, time, emoji_social_credit) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+        params![record.user_room_data_id, record.sender_user_id, record.kind, score_change.delta, score_change.new_social_credit, record.reason, epoch_secs, record.emoji_social_credit],
+    )?;
+    db_transaction.commit()?;
+
+    Ok(Some((old_social_credit, score_change)))
+}
+
+pub enum TransferResult {
+    /// The new social credit of the sender and the recipient
+    Done(i32, i32),
+    /// The sender would go below sender_bounds or the recipient above recipient_bounds
+    OutOfBounds,
+}
+
+/// Moves amount social credit from one user_room_data to another in a single database transaction, the transfer is refused
+/// if ScoreBounds::apply_change would limit one of the changes. Both changes are recorded with kind transfer,
+/// the owner of from_user_room_data_id as sender and the reason. Both user_room_data have to be in the same room
+pub fn transfer_social_credit(
+    conn: &Arc<Mutex<Connection>>,
+    from_user_room_data_id: i32,
+    to_user_room_data_id: i32,
+    amount: i32,
+    sender_bounds: &ScoreBounds,
+    recipient_bounds: &ScoreBounds,
+    reason: Option<&str>,
+) -> Result<TransferResult, Error> {
+    let mut connection = conn.lock().unwrap();
+    let db_transaction = connection.transaction()?;
+    let get_user_and_social_credit = |id: i32| db_transaction.query_row("SELECT user_id, social_credit FROM user_room_data WHERE id=?1", params![id], |row| Ok((row.get::<_, i32>(0)?, row.get::<_, i32>(1)?)));
+
+    let (sender_user_id, from_social_credit) = get_user_and_social_credit(from_user_room_data_id)?;
+    let from_change = sender_bounds.apply_change(from_social_credit, -amount);
+    let to_change = recipient_bounds.apply_change(get_user_and_social_credit(to_user_room_data_id)?.1, amount);
+    if from_change.clamped || to_change.clamped {
+        return Ok(TransferResult::OutOfBounds);
+    }
+
+    let epoch_secs = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap_or(Duration::from_secs(0)).as_secs() as i64;
+    let update_sql = "UPDATE user_room_data SET social_credit=?1 WHERE id=?2";
+    let insert_sql = "INSERT INTO credit_transaction (user_room_data_id, sender_user_id, kind, delta, social_credit, reason, time) VALUES (?1, ?2, 'transfer', ?3, ?4, ?5, ?6)";
+    db_transaction.execute(update_sql, params![from_change.new_social_credit, from_user_room_data_id])?;
+    db_transaction.execute(update_sql, params![to_change.new_social_credit, to_user_room_data_id])?;
+    db_transaction.execute(insert_sql, params![from_user_room_data_id, sender_user_id, from_change.delta, from_change.new_social_credit, reason, epoch_secs])?;
+    db_transaction.execute(insert_sql, params![to_user_room_data_id, sender_user_id, to_change.delta, to_change.new_social_credit, reason, epoch_secs])?;
+    db_transaction.commit()?;
+
+    Ok(TransferResult::Done(from_change.new_social_credit, to_change.new_social_credit))
+}
+
+/// A reaction from one user to another, used to find users that farm each other's score
+pub struct ReactionEdge {
+    pub sender_user_id: i32,
+    pub recipient_user_id: i32,
+    pub time: SystemTime,
+}
+
+/// Returns who reacted to whom in a room since the given time, oldest first
+pub fn find_reaction_edges_in_db(conn: &Arc<Mutex<Connection>>, room_id: &str, since: SystemTime) -> Option<Vec<ReactionEdge>> {
+    let sql = "SELECT credit_transaction.sender_user_id, user_room_data.user_id, credit_transaction.time FROM credit_transaction \
+                        INNER JOIN user_room_data ON credit_transaction.user_room_data_id=user_room_data.id \
+                        WHERE user_room_data.room_id=?1 AND credit_transaction.kind='reaction' AND credit_transaction.sender_user_id IS NOT NULL AND credit_transaction.time>=?2 \
+                        ORDER BY credit_transaction.time ASC, credit_transaction.id ASC";
+    let since_secs = since.duration_since(SystemTime::UNIX_EPOCH).unwrap_or(Duration::from_secs(0)).as_secs() as i64;
+    let connection = conn.lock().unwrap();
+
+    let edges: Result<Vec<ReactionEdge>, Error> = connection.prepare(sql)
+        .and_then(|mut stmt| {
+            stmt.query_map(params![room_id, since_secs], |row| {
+                Ok(ReactionEdge {
+                    sender_user_id: row.get(0)?,
+                    recipient_user_id: row.get(1)?,
+                    time: SystemTime::UNIX_EPOCH + Duration::from_secs(row.get::<_, i64>(2)?.max(0) as u64),
+                })
+            }).and_then(|mapped_rows| mapped_rows.collect())
+        });
+    match edges {
+        Ok(edges) => Some(edges),
+        Err(e) => {
+            println!("Database error: {}", e);
+            None
+        },
+    }
+}
+
+/// Reactions a user received and gave in a room, the sums are the social credit that was actually applied
+pub struct ReactionTotals {
+    pub received_count: i64,
+    pub received_sum: i64,
+    pub given_count: i64,
+    pub given_sum: i64,
+}
+
+pub fn find_reaction_totals_in_db(conn: &Arc<Mutex<Connection>>, user_id: i32, user_room_data_id: i32, room_id: &str) -> Option<ReactionTotals> {
+    let sql = "SELECT \
+                (SELECT COUNT(*) FROM credit_transaction WHERE user_room_data_id=?2 AND kind='reaction'), \
+                (SELECT COALESCE(SUM(delta), 0) FROM credit_transaction WHERE user_room_data_id=?2 AND kind='reaction'), \
+                COUNT(credit_transaction.id), COALESCE(SUM(credit_transaction.delta), 0) \
+                FROM credit_transaction INNER JOIN user_room_data ON credit_transaction.user_room_data_id=user_room_data.id \
+                WHERE credit_transaction.sender_user_id=?1 AND user_room_data.room_id=?3 AND credit_transaction.kind='reaction'";
+    let connection = conn.lock().unwrap();
+
+    let totals = connection.query_row(sql, params![user_id, user_room_data_id, room_id], |row| {
+        Ok(ReactionTotals {
+            received_count: row.get(0)?,
+            received_sum: row.get(1)?,
+            given_count: row.get(2)?,
+            given_sum: row.get(3)?,
+        })
+    });
+    match totals {
+        Ok(totals) => Some(totals),
+        Err(e) => {
+            println!("Database error: {}", e);
+            None
+        },
+    }
+}
+
+fn do_get_transaction_sql<P: Params>(
+    conn: &Arc<Mutex<Connection>>,
+    sql: &str,
+    params: P,
+) -> Result<Vec<Transaction>, Error> {
+    let connection = conn.lock().unwrap();
+    let mut stmt = match connection.prepare(sql) {
+        Ok(stmt) => stmt,
+        Err(e) => {
+            println!("Database error: {}", e);
+            return Err(e);
+        }
+    };
+
+    let transactions: Result<Vec<Transaction>, _> = stmt.query_map(params, |row| {
+        Ok(Transaction {
+            id: row.get(0)?,
+            user_room_data_id: row.get(1)?,
+            sender_user_id: row.get(2)?,
+            kind: row.get(3)?,
+            delta: row.get(4)?,
+            social_credit: row.get(5)?,
+            reason: row.get(6)?,
+            time: SystemTime::UNIX_EPOCH + Duration::from_secs(row.get::<_, i64>(7)?.max(0) as u64),
+            emoji_social_credit: row.get(8)?,
+        })
+    }).and_then(|mapped_rows| mapped_rows.collect());
+
+    transactions
+}
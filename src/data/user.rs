@@ -1,6 +1,5 @@
 use std::sync::{Arc, Mutex};
 use rusqlite::{Connection, Error, params, Params, Statement, ToSql};
-use crate::data::user_reaction::{get_user_reactions, UserReaction};
 use crate::data::user_room_data::UserRoomData;
 
 #[derive(Clone)]
@@ -13,8 +12,9 @@ pub enum UserType {
 #[derive(Clone)]
 pub struct User {
     pub id: i32,
-    pub name: String,
-    pub url: String,
+    pub name: String, // The localpart of the matrix id
+    pub url: String, // The server name of the matrix id
+    pub mxid: String, // The full matrix id, this is what identifies the user
     pub user_type: UserType,
     pub room_data: Option<UserRoomData>
 }
@@ -33,8 +33,19 @@ pub fn create_table_user(conn: &Connection) {
     )", []).expect("Failed to create user table");
 }
 
+/// Users that only differ in their server are different users, so the full matrix id is the identity key
+pub fn migrate_table_user_add_mxid(conn: &Connection) {
+    conn.execute("ALTER TABLE user ADD COLUMN mxid TEXT", []).expect("Failed to add mxid to user table");
+    conn.execute("UPDATE user SET mxid = '@' || name || ':' || url", []).expect("Failed to set mxid of existing users");
+    if let Err(e) = conn.execute("CREATE UNIQUE INDEX IF NOT EXISTS user_mxid ON user (mxid)", []) {
+        // Only possible if the same user was inserted twice before, the lookup still works without the constraint
+        println!("Unable to create unique index on user mxid, there are duplicate users: {}", e); // error level
+        conn.execute("CREATE INDEX IF NOT EXISTS user_mxid ON user (mxid)", []).expect("Failed to create index on user mxid");
+    }
+}
+
 pub fn insert_user(conn: &Arc<Mutex<Connection>>, user: &User) -> Result<(), Error> {
-    let sql = "INSERT INTO user (name, url, user_type) VALUES (?1, ?2, ?3)";
+    let sql = "INSERT INTO user (name, url, user_type, mxid) VALUES (?1, ?2, ?3, ?4)";
     let user_type_as_int = get_user_type_as_int(user);
     let connection = conn.lock().unwrap();
 
@@ -43,7 +54,8 @@ pub fn insert_user(conn: &Arc<Mutex<Connection>>, user: &User) -> Result<(), Err
         &[
             &user.name as &dyn ToSql,
             &user.url as &dyn ToSql,
-            &user_type_as_int as &dyn ToSql
+            &user_type_as_int as &dyn ToSql,
+            &user.mxid as &dyn ToSql
         ]
     )?;
 
@@ -66,6 +78,15 @@ pub fn update_user(conn: &Arc<Mutex<Connection>>, user: &User) -> Result<(), Err
     Ok(())
 }
 
+pub fn parse_user_type(text: &str) -> Option<UserType> {
+    match text.to_lowercase().as_str() {
+        "default" | "user" => Some(UserType::Default),
+        "moderator" | "mod" => Some(UserType::Moderator),
+        "admin" => Some(UserType::Admin),
+        _ => None,
+    }
+}
+
 fn get_user_type_as_int(user: &User) -> i32 {
     let user_type_as_int = match user.user_type {
         UserType::Default => 0,
@@ -75,16 +96,13 @@ fn get_user_type_as_int(user: &User) -> i32 {
     user_type_as_int
 }
 
-pub fn find_user_in_db(
-    conn: &Arc<Mutex<Connection>>,
-    name: &String, url: &String
-) -> Option<User> {
-    let sql = "SELECT * FROM user WHERE name=?1 AND url=?2";
-    let params = params![name, url];
+pub fn find_user_in_db(conn: &Arc<Mutex<Connection>>, mxid: &str) -> Option<User> {
+    let sql = "SELECT id, name, url, user_type, mxid FROM user WHERE mxid=?1";
+    let params = params![mxid];
     match do_get_user_sql(conn, sql, params) {
         Ok(mut users) => {
             if users.len() > 1 {
-                println!("Error: Multiple users found for name: {} and url: {}", name, url);
+                println!("Error: Multiple users found for {}", mxid);
             }
             users.pop()
         },
@@ -95,28 +113,16 @@ pub fn find_user_in_db(
     }
 }
 
-pub fn find_all_users_with_room_data_in_db(conn: &Arc<Mutex<Connection>>, room_id: &String) -> Option<Vec<User>> {
-    let sql = "SELECT user.id, user.name, user.url, user.user_type, user_room_data.id, user_room_data.user_id, user_room_data.room_id, user_room_data.social_credit \
-                        FROM user INNER JOIN user_room_data ON user.id=user_room_data.user_id WHERE user_room_data.room_id=?1 AND user.name NOT LIKE 'social-credit-system'";
-    let params = params![room_id];
-    let connection = conn.lock().unwrap();
-
-    let mut stmt = match connection.prepare(&sql) {
-        Ok(stmt) => stmt,
+pub fn find_user_by_id_in_db(conn: &Arc<Mutex<Connection>>, id: i32) -> Option<User> {
+    let sql = "SELECT id, name, url, user_type, mxid FROM user WHERE id=?1";
+    let params = params![id];
+    match do_get_user_sql(conn, sql, params) {
+        Ok(mut users) => users.pop(),
         Err(e) => {
             println!("Database error: {}", e);
-            return None;
-        }
-    };
-
-    let users = do_get_user_sql_inner(params, &mut stmt, &connection, true);
-
-    if users.is_err() {
-        println!("Database error: {}", users.err().unwrap());
-        return None;
+            None
+        },
     }
-
-    return Some(users.unwrap());
 }
 
 fn do_get_user_sql<P: Params>(
@@ -133,12 +139,12 @@ fn do_get_user_sql<P: Params>(
         }
     };
 
-    let users = do_get_user_sql_inner(params, &mut stmt, &connection, false);
+    let users = do_get_user_sql_inner(params, &mut stmt);
 
     return users;
 }
 
-fn do_get_user_sql_inner<P: Params>(params: P, stmt: &mut Statement, conn: &Connection, with_room_data: bool) -> Result<Vec<User>, Error> {
+fn do_get_user_sql_inner<P: Params>(params: P, stmt: &mut Statement) -> Result<Vec<User>, Error> {
     let users: Result<Vec<User>, _> = stmt.query_map(params, |row| {
         Ok(User {
             id: row.get(0)?,
@@ -150,19 +156,8 @@ fn do_get_user_sql_inner<P: Params>(params: P, stmt: &mut Statement, conn: &Conn
                 2 => UserType::Admin,
                 _ => UserType::Default,
             },
-            room_data: match with_room_data {
-                true => Some(UserRoomData {
-                    id: row.get(4)?,
-                    user_id: row.get(5)?,
-                    room_id: row.get(6)?,
-                    social_credit: row.get(7)?,
-                    reactions: get_user_reactions(conn, row.get(4)?)
-                        .or_else(|_| -> Result<Vec<UserReaction>, Error> {
-                            Ok(Vec::<UserReaction>::new())
-                        }).unwrap(),
-                }),
-                false => None,
-            },
+            mxid: row.get(4)?,
+            room_data: None,
         })
     }).and_then(|mapped_rows| mapped_rows.collect());
     users
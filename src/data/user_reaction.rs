@@ -1,5 +1,4 @@
 use std::time::SystemTime;
-use rusqlite::Error;
 
 #[derive(Clone)]
 pub struct UserReaction {
@@ -41,17 +40,6 @@ impl UserReaction {
     }
 }
 
-/// Deletes all reactions that are older than the given epoch time
-pub fn cleanup_table_user_reaction(conn: &rusqlite::Connection, epoch_time: i32) -> Result<(), rusqlite::Error> {
-    let sql = "DELETE FROM user_reaction WHERE time < ?1";
-
-    conn.execute(
-        sql,
-        [epoch_time]
-    )?;
-    Ok(())
-}
-
 pub fn create_table_user_reaction(conn: &rusqlite::Connection) {
     conn.execute("CREATE TABLE IF NOT EXISTS user_reaction (
                 id INTEGER PRIMARY KEY,
@@ -61,29 +49,21 @@ pub fn create_table_user_reaction(conn: &rusqlite::Connection) {
         )", []).expect("Failed to create user_reaction table");
 }
 
-pub fn get_user_reactions(conn: &rusqlite::Connection, user_room_data_id: i32) -> Result<Vec<UserReaction>, rusqlite::Error> {
-    let sql = "SELECT id, user_room_data_id, time, message_event_id FROM user_reaction WHERE user_room_data_id = ?1";
-
-    let mut stmt = conn.prepare(sql)?;
-    let mut rows = stmt.query([user_room_data_id])?;
-
-    let mut reactions = Vec::new();
-    while let Some(row) = rows.next()? {
-        let id: i32 = row.get(0)?;
-        let user_room_data_id: i32 = row.get(1)?;
-        let time: i64 = row.get(2)?;
-        let time = SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(time as u64);
-        let message_event_id: String = row.get(3)?;
+/// Makes the check for a second reaction to the same message fast, the table holds every reaction within REACTION_RETENTION_DAYS
+pub fn create_index_user_reaction_message(conn: &rusqlite::Connection) {
+    conn.execute("CREATE INDEX IF NOT EXISTS user_reaction_message ON user_reaction (user_room_data_id, message_event_id)", [])
+        .expect("Failed to create index on user_reaction table");
+}
 
-        reactions.push(UserReaction {
-            id,
-            user_room_data_id,
-            time,
-            message_event_id
-        });
-    }
+pub fn has_reacted_to_message(conn: &rusqlite::Connection, user_room_data_id: i32, message_event_id: &str) -> Result<bool, rusqlite::Error> {
+    let sql = "SELECT EXISTS(SELECT 1 FROM user_reaction WHERE user_room_data_id = ?1 AND message_event_id = ?2)";
+    conn.query_row(sql, rusqlite::params![user_room_data_id, message_event_id], |row| row.get(0))
+}
 
-    Ok(reactions)
+/// Deletes all reactions that are older than the given epoch time, returns the number of deleted reactions
+pub fn cleanup_table_user_reaction(conn: &rusqlite::Connection, epoch_time: i64) -> Result<usize, rusqlite::Error> {
+    let sql = "DELETE FROM user_reaction WHERE time < ?1";
+    conn.execute(sql, [epoch_time])
 }
 
 impl PartialOrd for UserReaction {
@@ -1,7 +1,7 @@
 use std::sync::{Arc, Mutex};
-use std::time::{Duration, SystemTime};
+use std::time::SystemTime;
 use rusqlite::{Connection, Error, params, Result};
-use crate::data::user_reaction::{get_user_reactions, UserReaction};
+use crate::data::user_reaction::{has_reacted_to_message, UserReaction};
 
 
 #[derive(Clone)]
@@ -10,62 +10,23 @@ pub struct UserRoomData {
     pub user_id: i32,
     pub room_id: String,
     pub social_credit: i32,
-    pub reactions: Vec<UserReaction>,
 }
 
 impl UserRoomData {
-    /// Checks if a user is able to react and change the social credit of another user,
-    /// returns 0 if the user can react,
-    /// returns the time in seconds until the user can react again otherwise
-    pub fn get_time_till_user_can_react(&self, reaction_period_minutes: i32, reaction_limit: i32) -> i64 {
-        let now = SystemTime::now();
-
-        // Filter out the reactions that are outside the reaction_period_minutes
-        let recent_reactions: Vec<_> = self.reactions.iter()
-            .filter(|&reaction| now.duration_since(reaction.time).unwrap() <= Duration::from_secs((reaction_period_minutes * 60) as u64))
-            .collect();
-
-        // If there are less than reaction_limit within the reaction_period_minutes, the user can react
-        if recent_reactions.len() < reaction_limit as usize {
-            return 0;
-        }
-
-        // Calculate the time until the most recent of these can expire
-        if let Some(latest_reaction) = recent_reactions.iter().max() {
-            if let Ok(time_since_latest) = now.duration_since(latest_reaction.time) {
-                let time_left = Duration::from_secs((reaction_period_minutes * 60) as u64) - time_since_latest;
-                return time_left.as_secs() as i64;
-            }
-        }
-
-        // In case of any unexpected issue, assume the user can react
-        0
+    /// A database error counts as already reacted, so a reaction is never applied twice
+    pub fn has_user_already_reacted_to_message_event_id(&self, conn: &Arc<Mutex<Connection>>, message_event_id: &str) -> bool {
+        has_reacted_to_message(&conn.lock().unwrap(), self.id, message_event_id).unwrap_or_else(|e| {
+            println!("Database error: {}", e); // error level
+            true
+        })
     }
 
-    pub fn has_user_already_reacted_to_message_event_id(&self, message_event_id: &String) -> bool {
-        self.reactions.iter().any(|reaction| reaction.message_event_id == *message_event_id)
-    }
-
-    pub fn add_reaction(&mut self, conn: &Arc<Mutex<Connection>>, reaction_period_minutes: i32, message_event_id: &String) {
-        let now = SystemTime::now();
-        let reaction = UserReaction::new(self.id, now, message_event_id.clone());
-        self.reactions.push(reaction.clone());
+    pub fn add_reaction(&self, conn: &Arc<Mutex<Connection>>, time: SystemTime, message_event_id: &String) {
+        let reaction = UserReaction::new(self.id, time, message_event_id.clone());
 
         if reaction.insert(&conn.lock().unwrap()).is_err() {
             println!("Failed to insert user reaction");
         }
-
-        // todo configurable (weekly) db cleanup
-        /*
-        let reaction_period_duration = Duration::from_secs((reaction_period_minutes * 60) as u64);
-        let prev = now - reaction_period_duration;
-        self.last_reactions.retain(|reaction| now.duration_since(reaction.time).unwrap_or(Duration::from_secs(0)) <= reaction_period_duration);
-        if cleanup_table_user_reaction(
-            &conn.lock().unwrap(),
-            prev.duration_since(SystemTime::UNIX_EPOCH).unwrap_or(Duration::from_secs(0)).as_secs() as i32).is_err()
-        {
-            println!("Failed to cleanup user reactions");
-        }*/
     }
 }
 
@@ -94,22 +55,6 @@ pub fn insert_user_room_data(conn: &Arc<Mutex<Connection>>, user_room_data: &Use
     Ok(())
 }
 
-pub fn update_user_room_data(conn: &Arc<Mutex<Connection>>, user_room_data: &UserRoomData) -> Result<(), Error> {
-    let sql = "UPDATE user_room_data SET social_credit=?1 WHERE user_id=?2 AND room_id=?3";
-    let connection = conn.lock().unwrap();
-
-    connection.execute(
-        sql,
-        params![
-            &user_room_data.social_credit,
-            &user_room_data.user_id,
-            &user_room_data.room_id,
-        ]
-    )?;
-
-    Ok(())
-}
-
 pub fn find_user_room_data_by_user_id_and_room_id(conn: &Arc<Mutex<Connection>>, user_id: i32, room_id: &String) -> Result<UserRoomData, Error> {
     let sql = "SELECT id, user_id, room_id, social_credit FROM user_room_data WHERE user_id=?1 AND room_id=?2";
     let connection = conn.lock().unwrap();
@@ -118,23 +63,11 @@ pub fn find_user_room_data_by_user_id_and_room_id(conn: &Arc<Mutex<Connection>>,
     let mut rows = stmt.query(params![&user_id, room_id])?;
 
     if let Some(row) = rows.next()? {
-        let reaction_result = get_user_reactions(&connection, row.get(0)?);
-        let reactions = if let Ok(r) = &reaction_result {
-            if r.is_empty() {
-                Vec::<UserReaction>::new()
-            } else {
-                r.clone() // Clone is required since `r` is a reference and we want to own the data
-            }
-        } else {
-            Vec::<UserReaction>::new()
-        };
-
         let user_room_data = UserRoomData {
             id: row.get(0)?,
             user_id: row.get(1)?,
             room_id: row.get(2)?,
             social_credit: row.get(3)?,
-            reactions: reactions
         };
 
         Ok(user_room_data)
@@ -143,3 +76,138 @@ pub fn find_user_room_data_by_user_id_and_room_id(conn: &Arc<Mutex<Connection>>,
         Err(Error::QueryReturnedNoRows)
     }
 }
+
+/// Returns the ids of all rooms that have at least one user with room data
+pub fn find_all_room_ids_in_db(conn: &Arc<Mutex<Connection>>) -> Result<Vec<String>, Error> {
+    let sql = "SELECT DISTINCT room_id FROM user_room_data UNION SELECT DISTINCT room_id FROM emoji ORDER BY room_id";
+    let connection = conn.lock().unwrap();
+
+    let mut stmt = connection.prepare(sql)?;
+    let room_ids: Result<Vec<String>, _> = stmt.query_map([], |row| row.get(0))
+        .and_then(|mapped_rows| mapped_rows.collect());
+    room_ids
+}
+
+/// Returns the room data of all users in the room
+pub fn find_all_user_room_data_for_room_in_db(conn: &Arc<Mutex<Connection>>, room_id: &str) -> Result<Vec<UserRoomData>, Error> {
+    let sql = "SELECT id, user_id, room_id, social_credit FROM user_room_data WHERE room_id=?1 ORDER BY id";
+    let connection = conn.lock().unwrap();
+
+    let mut stmt = connection.prepare(sql)?;
+    let room_data: Result<Vec<UserRoomData>, _> = stmt.query_map(params![room_id], |row| {
+        Ok(UserRoomData {
+            id: row.get(0)?,
+            user_id: row.get(1)?,
+            room_id: row.get(2)?,
+            social_credit: row.get(3)?,
+        })
+    }).and_then(|mapped_rows| mapped_rows.collect());
+    room_data
+}
+
+/// The score of a user in a room together with the position, users with the same score share a position
+pub struct Standing {
+    pub position: usize,
+    pub user_id: i32, // The id of the user in the database
+    pub mxid: String,
+    pub social_credit: i32,
+}
+
+/// Where a score is placed among the scores of a room
+pub struct Placement {
+    pub higher: usize, // Users with a higher score
+    pub same: usize, // Users with the same score, including the user itself
+    pub total: usize,
+    pub next_social_credit: Option<i32>, // The lowest score above this one
+}
+
+const ROOM_USERS_SQL: &str = "FROM user_room_data INNER JOIN user ON user.id=user_room_data.user_id WHERE user_room_data.room_id=?1 AND user.mxid!=?2";
+
+/// Returns a page of the scores of the room except the bot, the highest first or the lowest first if ascending is set
+pub fn find_standings_in_db(conn: &Arc<Mutex<Connection>>, room_id: &str, bot_user_id: &str, ascending: bool, limit: usize, offset: usize) -> Option<Vec<Standing>> {
+    let sql = format!(
+        "SELECT (SELECT COUNT(*) FROM user_room_data AS other INNER JOIN user AS other_user ON other_user.id=other.user_id \
+                 WHERE other.room_id=?1 AND other_user.mxid!=?2 AND other.social_credit>user_room_data.social_credit) + 1, \
+                user.id, user.mxid, user_room_data.social_credit {} ORDER BY user_room_data.social_credit {}, user.mxid ASC LIMIT ?3 OFFSET ?4",
+        ROOM_USERS_SQL, if ascending { "ASC" } else { "DESC" },
+    );
+    let connection = conn.lock().unwrap();
+
+    let standings: Result<Vec<Standing>, Error> = connection.prepare(&sql)
+        .and_then(|mut stmt| {
+            stmt.query_map(params![room_id, bot_user_id, limit as i64, offset as i64], |row| {
+                Ok(Standing {
+                    position: row.get::<_, i64>(0)? as usize,
+                    user_id: row.get(1)?,
+                    mxid: row.get(2)?,
+                    social_credit: row.get(3)?,
+                })
+            }).and_then(|mapped_rows| mapped_rows.collect())
+        });
+    match standings {
+        Ok(standings) => Some(standings),
+        Err(e) => {
+            println!("Database error: {}", e);
+            None
+        },
+    }
+}
+
+/// Places the score among the scores of the room except the bot
+pub fn find_placement_in_db(conn: &Arc<Mutex<Connection>>, room_id: &str, bot_user_id: &str, social_credit: i32) -> Option<Placement> {
+    let sql = format!(
+        "SELECT COUNT(*), COALESCE(SUM(user_room_data.social_credit>?3), 0), COALESCE(SUM(user_room_data.social_credit=?3), 0), \
+                MIN(CASE WHEN user_room_data.social_credit>?3 THEN user_room_data.social_credit END) {}",
+        ROOM_USERS_SQL,
+    );
+    let connection = conn.lock().unwrap();
+
+    let placement = connection.query_row(&sql, params![room_id, bot_user_id, social_credit], |row| {
+        Ok(Placement {
+            total: row.get::<_, i64>(0)? as usize,
+            higher: row.get::<_, i64>(1)? as usize,
+            same: row.get::<_, i64>(2)? as usize,
+            next_social_credit: row.get(3)?,
+        })
+    });
+    match placement {
+        Ok(placement) => Some(placement),
+        Err(e) => {
+            println!("Database error: {}", e);
+            None
+        },
+    }
+}
+
+/// Returns the number of users with room data in the room except the bot
+pub fn count_users_in_room_in_db(conn: &Arc<Mutex<Connection>>, room_id: &str, bot_user_id: &str) -> Option<usize> {
+    let sql = format!("SELECT COUNT(*) {}", ROOM_USERS_SQL);
+    let connection = conn.lock().unwrap();
+
+    match connection.query_row(&sql, params![room_id, bot_user_id], |row| row.get::<_, i64>(0)) {
+        Ok(count) => Some(count as usize),
+        Err(e) => {
+            println!("Database error: {}", e);
+            None
+        },
+    }
+}
+
+/// Returns the matrix id and the score of every user in the room except the bot
+pub fn find_all_scores_in_db(conn: &Arc<Mutex<Connection>>, room_id: &str, bot_user_id: &str) -> Option<Vec<(String, i32)>> {
+    let sql = format!("SELECT user.mxid, user_room_data.social_credit {} ORDER BY user_room_data.id", ROOM_USERS_SQL);
+    let connection = conn.lock().unwrap();
+
+    let scores: Result<Vec<(String, i32)>, Error> = connection.prepare(&sql)
+        .and_then(|mut stmt| {
+            stmt.query_map(params![room_id, bot_user_id], |row| Ok((row.get(0)?, row.get(1)?)))
+                .and_then(|mapped_rows| mapped_rows.collect())
+        });
+    match scores {
+        Ok(scores) => Some(scores),
+        Err(e) => {
+            println!("Database error: {}", e);
+            None
+        },
+    }
+}
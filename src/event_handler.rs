@@ -1,41 +1,127 @@
+use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+use matrix_sdk::attachment::AttachmentConfig;
 use matrix_sdk::room::{Joined, Room};
-use matrix_sdk::ruma::{events};
+use matrix_sdk::ruma::{events, OwnedUserId, UserId};
 use matrix_sdk::ruma::events::{AnySyncMessageLikeEvent, AnyTimelineEvent};
-use matrix_sdk::ruma::events::room::encrypted::Relation;
 use matrix_sdk::ruma::events::room::message::{MessageType, RoomMessageEventContent};
 use rusqlite::Connection;
+use crate::config::{BackupConfig, Config};
+use crate::data::budget::add_budget_spending;
+use crate::data::consequence::{delete_consequence, find_consequence_in_db, insert_consequence};
 use crate::data::emoji::{Emoji, find_emoji_in_db, insert_emoji};
 use crate::data::event::{Event, find_event_in_db, insert_event};
-use crate::data::user::{update_user, User, UserType};
-use crate::data::user_room_data::update_user_room_data;
+use crate::data::rank::{delete_rank, find_all_ranks_for_room_in_db, insert_rank, Rank};
+use crate::data::room_setting::{delete_room_setting, find_room_settings_in_db, set_room_setting};
+use crate::data::season::{end_season, find_season_in_db, find_season_standings_in_db};
+use crate::data::transaction::{change_social_credit, find_reaction_totals_in_db, find_reaction_transactions_by_sender_in_db, find_transactions_by_sender_in_db, Transaction, transfer_social_credit, TransferResult};
+use crate::data::user::{find_user_in_db, User, UserType};
+use crate::data::user_room_data::find_user_room_data_by_user_id_and_room_id;
+use crate::utils::backup_util::create_backup;
+use crate::utils::budget_util::{BudgetConfig, format_duration};
+use crate::utils::collusion_util::{CollusionAction, CollusionConfig, get_suspicious_answer, SuspicionCache};
+use crate::utils::consequence_util::{evaluate_consequences, format_consequence, get_consequence_list_answer, parse_consequence, revert_all_applied_consequences};
+use crate::utils::cooldown_util::{CooldownConfig, get_time_till_user_can_transfer};
 use crate::utils::emoji_util::get_emoji_list_answer;
-use crate::utils::user_util::{compare_user, extract_userdata_from_string, get_user_list_answer, setup_user};
+use crate::utils::export_util::{export_data, ExportFormat, serialize_export_data};
+use crate::utils::mention_util::{find_mentioned_user_id, find_mentioned_user_id_and_following_text, get_display_name, MentionMessage};
+use crate::utils::notice_util::{NoticeMode, send_user_notice};
+use crate::utils::rank_util::{get_rank, get_rank_list_answer, parse_rank_range, push_rank_change};
+use crate::utils::room_config_util::{find_room_setting, get_room_config_answer, get_switch_setting};
+use crate::utils::season_util::{get_season_list_answer, get_season_standings_message};
+use crate::utils::score_util::{ReactionWeights, scale_delta, ScoreBounds};
+use crate::utils::user_util::{compare_user, disambiguate_names, get_leaderboard_answer, get_placement_answer, get_user_list_answer, setup_user};
+use crate::web::pages::{escape_html, format_time};
 
+const DEFAULT_COOLDOWN_NOTICE_INTERVAL_MINUTES: u64 = 1;
+const DEFAULT_TRANSFER_LIMIT: i32 = 5;
+const DEFAULT_SEASON_TOP: usize = 3;
+const DEFAULT_LIST_PAGE_SIZE: usize = 20;
+const DEFAULT_LEADERBOARD_SIZE: usize = 10;
+const MAX_LEADERBOARD_SIZE: usize = 50;
+
+/// A change of the social credit of a user caused by a reaction
+pub struct CreditChange {
+    pub sender_id: OwnedUserId,
+    pub sender_name: String,
+    pub recipient_id: OwnedUserId,
+    pub recipient_name: String,
+    pub emoji: String,
+    pub old_social_credit: i32,
+    pub new_social_credit: i32,
+    /// The multiplier of the reaction weights of the room for the sender
+    pub multiplier: f64,
+    /// The change was limited by the score bounds of the room
+    pub clamped: bool,
+}
+
+impl CreditChange {
+    /// Appends "sender changed recipient's Social Credit Score ..." with pills for both users
+    pub fn push_to(&self, message: &mut MentionMessage) {
+        message.push_mention(&self.sender_id, &self.sender_name);
+        message.push(" changed ", " changed ");
+        message.push_mention(&self.recipient_id, &self.recipient_name);
+        let emoji = if self.multiplier != 1.0 {
+            let multiplier = format!("{:.2}", self.multiplier);
+            format!("{} (x{})", self.emoji, multiplier.trim_end_matches('0').trim_end_matches('.'))
+        }
+        else {
+            self.emoji.clone()
+        };
+        message.push(
+            &format!("'s Social Credit Score using {} from {} to {}", emoji, self.old_social_credit, self.new_social_credit),
+            &format!("'s Social Credit Score using {} from <b>{}</b> to <b>{}</b>", escape_html(&emoji), self.old_social_credit, self.new_social_credit),
+        );
+        if self.clamped {
+            message.push(" (limited by the score limits of the room)", " <i>(limited by the score limits of the room)</i>");
+        }
+    }
+}
+
+pub enum ReactionOutcome {
+    Applied(CreditChange),
+    /// The sender has to wait the given number of seconds before reacting again
+    Cooldown(i64),
+    /// The change costs more than the remaining budget of the sender, which is reset in the given number of seconds
+    OverBudget { remaining: i64, cost: i64, time_till_reset: i64 },
+    Ignored,
+}
 
 pub struct EventHandler {
     conn: Arc<Mutex<Connection>>,
-    bot_username: String,
-    homeserver_url: String,
-    homeserver_url_without_protocol: String,
+    db_path: String,
+    bot_user_id: OwnedUserId,
     initial_social_credit: i32,
     reaction_period_minutes: i32,
     reaction_limit: i32,
+    backup_config: Option<BackupConfig>,
+    reaction_retention: Duration,
+    /// When each user was last told about their cooldown in each room
+    last_cooldown_notices: Mutex<HashMap<(String, String), SystemTime>>,
+    suspicion_cache: SuspicionCache,
 }
 
 impl EventHandler {
-    pub fn new(conn: Arc<Mutex<Connection>>, bot_username: String, homeserver_url: String, initial_social_credit: i32, reaction_period_minutes: i32, reaction_limit: i32) -> Self {
+    pub fn new(conn: Arc<Mutex<Connection>>, config: &Config, bot_user_id: OwnedUserId) -> Self {
         EventHandler {
             conn,
-            bot_username,
-            homeserver_url: homeserver_url.clone(),
-            homeserver_url_without_protocol: homeserver_url.strip_prefix("https://").unwrap_or(homeserver_url.strip_prefix("http://").unwrap_or(&homeserver_url)).to_string(),
-            initial_social_credit,
-            reaction_period_minutes,
-            reaction_limit,
+            db_path: config.db_path.clone(),
+            bot_user_id,
+            initial_social_credit: config.initial_social_credit,
+            reaction_period_minutes: config.reaction_timespan,
+            reaction_limit: config.reaction_limit,
+            backup_config: config.backup.clone(),
+            reaction_retention: Duration::from_secs(config.maintenance.reaction_retention_days * 24 * 60 * 60),
+            last_cooldown_notices: Mutex::new(HashMap::new()),
+            suspicion_cache: SuspicionCache::default(),
         }
     }
 
+    pub fn conn(&self) -> &Arc<Mutex<Connection>> {
+        &self.conn
+    }
+
     pub async fn on_message_like_event(&self, event: AnySyncMessageLikeEvent, room: Room) {
         match room {
             Room::Joined(room) => {
@@ -44,7 +130,7 @@ impl EventHandler {
                 if self.check_and_handle_event_already_handled(&event) { return; }
                 if self.handle_sender_is_the_bot(&event) { return; }
 
-                let sender = setup_user(&self.conn, Some(room.clone()), &event.sender().to_string(), UserType::Default, self.initial_social_credit);
+                let sender = setup_user(&self.conn, Some(room.room_id().as_str()), &event.sender().to_string(), UserType::Default, self.initial_social_credit);
                 if sender.is_none() {
                     println!("Sender is none"); // debug level
                     return;
@@ -61,124 +147,26 @@ impl EventHandler {
 
                 if event.event_type().to_string() == "m.reaction" {
                     let sender = sender.clone().unwrap();
-                    if event.original_content().is_none() {
-                        println!("Received a m.reaction event without original_content. Event: {:?}", event); // debug level
-                        return;
-                    }
-
-                    match event.original_content().unwrap() {
-                        events::AnyMessageLikeEventContent::Reaction(content) => {
-                            println!("Reaction content {:?}", content);
-                            let mut emoji_text = content.relates_to.key.clone();
-                            if emoji_text.ends_with("\u{fe0f}") {
-                                emoji_text = emoji_text.replace("\u{fe0f}", "");
-                            }
-
-                            let emoji = find_emoji_in_db(&self.conn, &emoji_text, &room.room_id().to_string());
-                            if emoji.is_none() {
-                                println!("Emoji {} is not registered", content.relates_to.key); // debug level
-                                return;
-                            }
-                            let emoji = emoji.unwrap();
-
-                            let relation = &event.original_content().unwrap().relation();
-                            if relation.is_none() {
-                                println!("Relation is none");
-                                return;
-                            }
-
-                            if sender.room_data.is_none() {
-                                println!("Sender of reaction does not have room data"); // error level
-                                return;
-                            }
-
-                            let sender_user_room_data = sender.clone().room_data.unwrap();
-                            let time_till_user_can_react = sender_user_room_data.get_time_till_user_can_react(self.reaction_period_minutes, self.reaction_limit);
-                            if time_till_user_can_react > 0 {
-                                let minutes = time_till_user_can_react / 60;
-                                let seconds = time_till_user_can_react % 60;
-                                let text = format!("{}, you are still on cooldown, remaining time: {}m {}s", sender.name, minutes, seconds);
-                                room.send(RoomMessageEventContent::text_html(
-                                    text.clone(),
-                                    text
-                                ), None).await.unwrap();
-                                return;
-                            }
-
-                            match relation.clone().unwrap().clone() {
-                                Relation::Annotation(annotation) => {
-                                    let message_event = room.event(&*annotation.event_id).await;
-                                    if message_event.is_err() {
-                                        println!("Unable to get the message event that relates to this reaction event"); // error level
-                                        return;
-                                    }
-
-                                    let message_event = message_event.unwrap().event;
-                                    let deserialized_event = match message_event.deserialize() {
-                                        Ok(event) => event,
-                                        Err(e) => {
-                                            println!("Unable to deserialize message event: {}", e); // error level
-                                            return;
-                                        }
-                                    };
-                                    match deserialized_event {
-                                        AnyTimelineEvent::MessageLike(message_like_event) => {
-                                            println!("Message like event {:?}", message_like_event);
-                                            println!("Sender: {}", message_like_event.sender().to_string());
-
-                                            // The sender here is the user where the social credit score should be changed, so it is the recipient of the reaction
-                                            let recipient_user_tag = message_like_event.sender().to_string();
-                                            let recipient_opt = setup_user(&self.conn, Some(room.clone()), &recipient_user_tag, UserType::Default, self.initial_social_credit);
-                                            if recipient_opt.is_none() {
-                                                println!("Recipient of reaction is none");
-                                                return;
-                                            }
-                                            let mut recipient = recipient_opt.clone().unwrap();
-
-                                            if self.is_user_the_bot(&recipient.name, &recipient.url) {
-                                                println!("Recipient of reaction is the bot itself"); // debug level
-                                                return;
-                                            }
-
-                                            if sender_user_room_data.has_user_already_reacted_to_message_event_id(&message_like_event.event_id().to_string()) {
-                                                println!("Sender @{}:{} already reacted to this message event: {}", sender.name, sender.url, event.event_id()); // debug level
-                                                return;
-                                            }
-
-                                            let sender_clone = sender.clone();
-
-                                            if compare_user(&recipient, &sender_clone) {
-                                                println!("Sender and recipient of reaction are the same user"); // debug level
-                                                return;
-                                            }
-
-                                            if recipient.room_data.is_none() {
-                                                println!("Recipient of reaction does not have room data"); // error level
-                                                return;
-                                            }
-
-                                            let mut recipient_room_data = recipient.room_data.unwrap();
-                                            let old_social_credit = recipient_room_data.social_credit;
-                                            recipient_room_data.social_credit += emoji.social_credit;
-                                            recipient.room_data = Some(recipient_room_data);
-
-                                            // Update sender reactions
-                                            self.update_user_in_db(&recipient);
-                                            sender.room_data.unwrap().add_reaction(&self.conn, self.reaction_period_minutes, &message_like_event.event_id().to_string());
-
-                                            let text = format!("<b>{}</b> changed <b>{}'s</b> Social Credit Score using {} from <b>{}</b> to <b>{}</b>", sender.name, recipient.name, emoji.emoji, old_social_credit, recipient.room_data.unwrap().social_credit);
-                                            room.send(RoomMessageEventContent::text_html(
-                                                text.clone(),
-                                                text
-                                            ), None).await.unwrap();
-                                        },
-                                        _ => {}
-                                    }
-                                },
-                                _ => {}
-                            }
-                        }
-                        _ => {}
+                    match self.apply_reaction(&room, &sender, &event, get_event_time(&event)).await {
+                        ReactionOutcome::Applied(change) => {
+                            let mut message = MentionMessage::default();
+                            change.push_to(&mut message);
+                            let ranks = find_all_ranks_for_room_in_db(&self.conn, room.room_id().as_str()).unwrap_or_default();
+                            push_rank_change(&mut message, &ranks, &change.recipient_id, &change.recipient_name, change.old_social_credit, change.new_social_credit);
+                            let ping = get_switch_setting(&find_room_settings_in_db(&self.conn, room.room_id().as_str()), "ping_changes", true);
+                            let ping_user_ids: Vec<&UserId> = if ping { vec![&change.recipient_id] } else { Vec::new() };
+                            message.send(&room, &ping_user_ids).await;
+                            evaluate_consequences(&self.conn, &room, &self.bot_user_id, &change.recipient_id, change.new_social_credit).await;
+                        },
+                        ReactionOutcome::Cooldown(time_till_user_can_react) => {
+                            let text = format!("you are still on cooldown, remaining time: {}m {}s", time_till_user_can_react / 60, time_till_user_can_react % 60);
+                            self.send_cooldown_notice(&room, &event, &text).await;
+                        },
+                        ReactionOutcome::OverBudget { remaining, cost, time_till_reset } => {
+                            let text = format!("this reaction costs {} but only {} of your budget is left, it resets in {}", cost, remaining, format_duration(time_till_reset));
+                            self.send_cooldown_notice(&room, &event, &text).await;
+                        },
+                        ReactionOutcome::Ignored => {},
                     }
                 }
 
@@ -192,10 +180,10 @@ impl EventHandler {
 
                     match event.original_content().unwrap() {
                         events::AnyMessageLikeEventContent::RoomMessage(content) => {
-                            match content.msgtype {
-                                MessageType::Text(..) => {},
+                            let formatted_body = match &content.msgtype {
+                                MessageType::Text(text) => text.formatted.as_ref().map(|formatted| formatted.body.clone()),
                                 _ => { return; }
-                            }
+                            };
 
                             let body = content.body();
 
@@ -208,6 +196,18 @@ impl EventHandler {
                             if self.handle_help(&room, &mut stripped_body).await { return; };
                             if self.handle_list(&room, &mut stripped_body).await { return; };
                             if self.handle_list_emojis(&room, &mut stripped_body).await { return; };
+                            if self.handle_leaderboard(&room, &sender, &stripped_body, formatted_body.as_deref()).await { return; }
+                            if self.handle_score(&room, &sender, &stripped_body, formatted_body.as_deref()).await { return; }
+                            if self.handle_export(&room, &sender, &stripped_body).await { return; }
+                            if self.handle_backup(&room, &sender, &stripped_body).await { return; }
+                            if self.handle_config(&room, &sender, &stripped_body).await { return; }
+                            if self.handle_ranks(&room, &sender, &stripped_body).await { return; }
+                            if self.handle_consequences(&room, &sender, &stripped_body).await { return; }
+                            if self.handle_suspicious(&room, &sender, &stripped_body).await { return; }
+                            if self.handle_budget(&room, &sender, &stripped_body).await { return; }
+                            if self.handle_give(&room, &sender, &stripped_body, formatted_body.as_deref()).await { return; }
+                            if self.handle_score_correction(&room, &sender, &stripped_body, formatted_body.as_deref()).await { return; }
+                            if self.handle_season(&room, &sender, &stripped_body).await { return; }
                             if self.handle_register_emoji(room, &mut sender, &mut stripped_body).await { return; }
                         }
                         _ => {}
@@ -218,6 +218,229 @@ impl EventHandler {
         }
     }
 
+    /// Applies a reaction with a registered emoji to the social credit of the sender of the message
+    /// that was reacted to, the cooldown of the reacting user is evaluated at the time as_of
+    async fn apply_reaction(&self, room: &Joined, sender: &User, event: &AnySyncMessageLikeEvent, as_of: SystemTime) -> ReactionOutcome {
+        let content = match event.original_content() {
+            Some(events::AnyMessageLikeEventContent::Reaction(content)) => content,
+            Some(_) => return ReactionOutcome::Ignored,
+            None => {
+                println!("Received a m.reaction event without original_content. Event: {:?}", event); // debug level
+                return ReactionOutcome::Ignored;
+            }
+        };
+        println!("Reaction content {:?}", content);
+
+        let mut emoji_text = content.relates_to.key.clone();
+        if emoji_text.ends_with('\u{fe0f}') {
+            emoji_text = emoji_text.replace('\u{fe0f}', "");
+        }
+
+        let emoji = match find_emoji_in_db(&self.conn, &emoji_text, &room.room_id().to_string()) {
+            Some(emoji) => emoji,
+            None => {
+                println!("Emoji {} is not registered", content.relates_to.key); // debug level
+                return ReactionOutcome::Ignored;
+            }
+        };
+
+        let sender_user_room_data = match sender.room_data.clone() {
+            Some(room_data) => room_data,
+            None => {
+                println!("Sender of reaction does not have room data"); // error level
+                return ReactionOutcome::Ignored;
+            }
+        };
+
+        let room_id = room.room_id().to_string();
+        let settings = find_room_settings_in_db(&self.conn, &room_id);
+        let cooldown_config = CooldownConfig::from_settings(&settings, self.reaction_limit, self.reaction_period_minutes);
+        let history_start = as_of.checked_sub(cooldown_config.history_span()).unwrap_or(SystemTime::UNIX_EPOCH);
+        let history = find_reaction_transactions_by_sender_in_db(&self.conn, sender.id, &room_id, history_start).unwrap_or_default();
+        let time_till_user_can_react = cooldown_config.get_time_till_user_can_react(&history, as_of, emoji.social_credit);
+        if time_till_user_can_react > 0 {
+            return ReactionOutcome::Cooldown(time_till_user_can_react);
+        }
+
+        let message_event = match room.event(&content.relates_to.event_id).await {
+            Ok(message_event) => message_event.event,
+            Err(_) => {
+                println!("Unable to get the message event that relates to this reaction event"); // error level
+                return ReactionOutcome::Ignored;
+            }
+        };
+        let message_like_event = match message_event.deserialize() {
+            Ok(AnyTimelineEvent::MessageLike(message_like_event)) => message_like_event,
+            Ok(_) => return ReactionOutcome::Ignored,
+            Err(e) => {
+                println!("Unable to deserialize message event: {}", e); // error level
+                return ReactionOutcome::Ignored;
+            }
+        };
+        println!("Message like event {:?}", message_like_event);
+        println!("Sender: {}", message_like_event.sender());
+
+        // The sender here is the user where the social credit score should be changed, so it is the recipient of the reaction
+        let recipient_user_tag = message_like_event.sender().to_string();
+        let recipient = match setup_user(&self.conn, Some(room.room_id().as_str()), &recipient_user_tag, UserType::Default, self.initial_social_credit) {
+            Some(recipient) => recipient,
+            None => {
+                println!("Recipient of reaction is none");
+                return ReactionOutcome::Ignored;
+            }
+        };
+
+        if recipient.mxid == self.bot_user_id.as_str() {
+            println!("Recipient of reaction is the bot itself"); // debug level
+            return ReactionOutcome::Ignored;
+        }
+
+        // Reactions older than the retention are pruned, so a second reaction to messages that old could not be detected
+        let message_time = message_like_event.origin_server_ts().to_system_time().unwrap_or(SystemTime::UNIX_EPOCH);
+        if SystemTime::now().duration_since(message_time).unwrap_or(Duration::ZERO) > self.reaction_retention {
+            println!("Message {} is older than the reaction retention", message_like_event.event_id()); // debug level
+            return ReactionOutcome::Ignored;
+        }
+
+        if sender_user_room_data.has_user_already_reacted_to_message_event_id(&self.conn, message_like_event.event_id().as_str()) {
+            println!("Sender {} already reacted to this message event: {}", sender.mxid, event.event_id()); // debug level
+            return ReactionOutcome::Ignored;
+        }
+
+        if compare_user(&recipient, sender) {
+            println!("Sender and recipient of reaction are the same user"); // debug level
+            return ReactionOutcome::Ignored;
+        }
+
+        let recipient_room_data = match recipient.room_data {
+            Some(room_data) => room_data,
+            None => {
+                println!("Recipient of reaction does not have room data"); // error level
+                return ReactionOutcome::Ignored;
+            }
+        };
+        let time_till_user_can_change_recipient = cooldown_config.get_time_till_user_can_change_recipient(&history, recipient_room_data.id, as_of);
+        if time_till_user_can_change_recipient > 0 {
+            return ReactionOutcome::Cooldown(time_till_user_can_change_recipient);
+        }
+
+        let mut multiplier = ReactionWeights::from_settings(&settings).map_or(1.0, |weights| weights.get_multiplier(sender_user_room_data.social_credit));
+        if let Some(collusion_config) = CollusionConfig::from_settings(&settings).filter(|config| config.action == CollusionAction::Dampen) {
+            if self.suspicion_cache.is_suspicious(&self.conn, &room_id, &collusion_config, sender.id, recipient.id, as_of) {
+                println!("Dampening suspicious reaction from {} to {}", sender.mxid, recipient.mxid); // debug level
+                multiplier *= collusion_config.dampen_percent as f64 / 100.0;
+            }
+        }
+        let delta = scale_delta(emoji.social_credit, multiplier);
+        let bounds = ScoreBounds::from_settings(&settings);
+        let budget_config = BudgetConfig::from_settings(&settings);
+        let remaining_budget = budget_config.as_ref().map(|config| config.get_remaining_budget(&self.conn, sender_user_room_data.id, as_of));
+
+        // The budget is checked against the current score within the database transaction, as the cost depends on how much the bounds limit the change
+        let mut cost = 0;
+        let mut record = Transaction::new(recipient_room_data.id, Some(sender.id), "reaction", 0, 0, Some(emoji.emoji.clone()));
+        record.time = as_of;
+        record.emoji_social_credit = Some(emoji.social_credit);
+        let result = change_social_credit(&self.conn, &record, &bounds, |social_credit| {
+            cost = (bounds.apply_change(social_credit, delta).delta as i64).abs();
+            Some(delta).filter(|_| remaining_budget.map_or(true, |remaining| cost <= remaining))
+        });
+        let (old_social_credit, score_change) = match (result, budget_config.as_ref().zip(remaining_budget)) {
+            (Ok(Some(change)), _) => change,
+            (Ok(None), Some((config, remaining))) => {
+                return ReactionOutcome::OverBudget { remaining, cost, time_till_reset: config.get_time_till_reset(as_of) };
+            },
+            (Ok(None), None) => return ReactionOutcome::Ignored,
+            (Err(e), _) => {
+                println!("Unable to change the social credit of {}: {}", recipient.mxid, e); // error level
+                return ReactionOutcome::Ignored;
+            }
+        };
+
+        sender_user_room_data.add_reaction(&self.conn, as_of, &message_like_event.event_id().to_string());
+        if let Some(config) = &budget_config {
+            if let Err(e) = add_budget_spending(&self.conn, sender_user_room_data.id, config.get_period_start(as_of), cost) {
+                println!("Unable to update budget in db: {}", e); // error level
+            }
+        }
+
+        let recipient_id = message_like_event.sender().to_owned();
+        let mut names = [
+            (event.sender().to_string(), get_display_name(room, event.sender()).await),
+            (recipient_id.to_string(), get_display_name(room, &recipient_id).await),
+        ];
+        disambiguate_names(&mut names);
+        let [(_, sender_name), (_, recipient_name)] = names;
+        ReactionOutcome::Applied(CreditChange {
+            sender_name,
+            sender_id: event.sender().to_owned(),
+            recipient_name,
+            recipient_id,
+            emoji: emoji.emoji,
+            old_social_credit,
+            new_social_credit: score_change.new_social_credit,
+            multiplier,
+            clamped: score_change.clamped,
+        })
+    }
+
+    /// Tells the user that they are on cooldown the way the room chose, at most once per cooldown_notice_interval
+    /// so reacting again and again does not spam the room
+    async fn send_cooldown_notice(&self, room: &Joined, event: &AnySyncMessageLikeEvent, text: &str) {
+        let room_id = room.room_id().to_string();
+        let settings = find_room_settings_in_db(&self.conn, &room_id);
+        let mode = settings.get("cooldown_notice").and_then(|value| NoticeMode::parse(value)).unwrap_or(NoticeMode::Room);
+        if mode == NoticeMode::Silent {
+            return;
+        }
+
+        let interval_minutes = settings.get("cooldown_notice_interval").and_then(|value| value.parse::<u64>().ok()).unwrap_or(DEFAULT_COOLDOWN_NOTICE_INTERVAL_MINUTES);
+        let now = SystemTime::now();
+        {
+            let mut last_cooldown_notices = self.last_cooldown_notices.lock().unwrap();
+            let key = (room_id.clone(), event.sender().to_string());
+            if let Some(last_notice) = last_cooldown_notices.get(&key) {
+                if now.duration_since(*last_notice).unwrap_or(Duration::ZERO) < Duration::from_secs(interval_minutes * 60) {
+                    println!("Cooldown notice for {} suppressed", event.sender()); // debug level
+                    return;
+                }
+            }
+            last_cooldown_notices.insert(key, now);
+        }
+
+        let mut message = MentionMessage::default();
+        match mode {
+            NoticeMode::Dm => {
+                let text = format!("In {}, {}", room.name().unwrap_or(room_id), text);
+                message.push(&text, &escape_html(&text));
+            },
+            _ => {
+                message.push_mention(event.sender(), &get_display_name(room, event.sender()).await);
+                let text = format!(", {}", text);
+                message.push(&text, &escape_html(&text));
+            },
+        }
+        let ping = get_switch_setting(&settings, "ping_cooldown", true);
+        let reacted_event_id = match event.original_content() {
+            Some(events::AnyMessageLikeEventContent::Reaction(content)) => Some(content.relates_to.event_id),
+            _ => None,
+        };
+        send_user_notice(room, &mode, event.sender(), reacted_event_id.as_deref(), message, ping).await;
+    }
+
+    /// Handles an event that was sent while the bot was offline, only reactions are applied,
+    /// commands are not answered anymore. Returns None if the event was handled before
+    pub async fn catch_up_event(&self, event: &AnySyncMessageLikeEvent, room: &Joined) -> Option<ReactionOutcome> {
+        if self.check_and_handle_event_already_handled(event) { return None; }
+        if self.handle_sender_is_the_bot(event) { return None; }
+        if event.event_type().to_string() != "m.reaction" {
+            return Some(ReactionOutcome::Ignored);
+        }
+
+        let sender = setup_user(&self.conn, Some(room.room_id().as_str()), &event.sender().to_string(), UserType::Default, self.initial_social_credit)?;
+        Some(self.apply_reaction(room, &sender, event, get_event_time(event)).await)
+    }
+
     fn check_and_handle_event_already_handled(&self, event: &AnySyncMessageLikeEvent) -> bool {
         let handled_event = find_event_in_db(&self.conn, &event.event_id().to_string());
         if handled_event.is_some() {
@@ -229,6 +452,7 @@ impl EventHandler {
             id: event.event_id().to_string(),
             event_type: event.event_type().to_string(),
             handled: true,
+            time: SystemTime::now(),
         };
         if insert_event(&self.conn, &new_handled_event).is_err() {
             println!("Unable to insert event {} into db", new_handled_event.id); // debug level
@@ -238,25 +462,135 @@ impl EventHandler {
     }
 
     fn handle_sender_is_the_bot(&self, event: &AnySyncMessageLikeEvent) -> bool {
-        let sender_userdata = extract_userdata_from_string(event.sender().to_string().as_str());
-        if sender_userdata.is_some() {
-            let sender_userdata = sender_userdata.unwrap();
-            if self.is_user_the_bot(&*sender_userdata.0, &*sender_userdata.1) {
-                println!("Received a message from the bot itself, event: {:?}", event); // debug level
-                return true;
-            }
+        if event.sender() == self.bot_user_id {
+            println!("Received a message from the bot itself, event: {:?}", event); // debug level
+            return true;
         }
         false
     }
 
     async fn handle_list(&self, room: &Joined, stripped_body: &mut String) -> bool {
-        if stripped_body == "!list" {
-            let answer = get_user_list_answer(&self.conn, &room);
-            let content = RoomMessageEventContent::text_html(answer.text, answer.html);
-            room.send(content, None).await.unwrap();
-            true;
+        let page = match stripped_body.strip_prefix("!list") {
+            Some("") => 1,
+            Some(args) if args.starts_with(' ') => match args.trim().parse::<usize>() {
+                Ok(page) if page > 0 => page,
+                _ => {
+                    room.send(RoomMessageEventContent::text_plain("Invalid command usage! Example: !list 2"), None).await.unwrap();
+                    return true;
+                }
+            },
+            _ => return false,
+        };
+
+        let settings = find_room_settings_in_db(&self.conn, room.room_id().as_str());
+        let page_size = settings.get("list_page_size").and_then(|value| value.parse::<usize>().ok()).filter(|size| *size > 0).unwrap_or(DEFAULT_LIST_PAGE_SIZE);
+        let (message, user_ids) = get_user_list_answer(&self.conn, room, &self.bot_user_id, page, page_size).await;
+        let ping = get_switch_setting(&settings, "ping_list", false);
+        let ping_user_ids: Vec<&UserId> = if ping { user_ids.iter().map(|user_id| user_id.as_ref()).collect() } else { Vec::new() };
+        message.send(room, &ping_user_ids).await;
+        true
+    }
+
+    /// Handles !top and !bottom with an optional number of users and !rank with an optional user, defaulting to the sender
+    async fn handle_leaderboard(&self, room: &Joined, sender: &User, body: &str, formatted_body: Option<&str>) -> bool {
+        let (command, args) = body.split_once(' ').unwrap_or((body, ""));
+        let args = args.trim();
+        match command {
+            "!top" | "!bottom" => {
+                let count = match args {
+                    "" => DEFAULT_LEADERBOARD_SIZE,
+                    _ => match args.parse::<usize>() {
+                        Ok(count) if count > 0 => count.min(MAX_LEADERBOARD_SIZE),
+                        _ => {
+                            room.send(RoomMessageEventContent::text_plain(format!("Invalid command usage! Example: {} 10", command)), None).await.unwrap();
+                            return true;
+                        }
+                    },
+                };
+                get_leaderboard_answer(&self.conn, room, &self.bot_user_id, command == "!bottom", count).await.send(room, &[]).await;
+            },
+            "!rank" => {
+                let user_id = match args {
+                    "" => UserId::parse(sender.mxid.as_str()).ok(),
+                    _ => parse_user(args, formatted_body),
+                };
+                match user_id {
+                    Some(user_id) => get_placement_answer(&self.conn, room, &self.bot_user_id, &user_id).await.send(room, &[]).await,
+                    None => {
+                        room.send(RoomMessageEventContent::text_plain("Invalid command usage! Example: !rank or !rank @bob:example.org"), None).await.unwrap();
+                    }
+                }
+            },
+            _ => return false,
         }
-        false
+        true
+    }
+
+    /// Shows the score, rank, reaction totals and cooldown of a user, defaulting to the sender
+    async fn handle_score(&self, room: &Joined, sender: &User, body: &str, formatted_body: Option<&str>) -> bool {
+        let args = match body.strip_prefix("!score") {
+            Some(args) if args.is_empty() || args.starts_with(' ') => args.trim(),
+            _ => return false,
+        };
+        let user_id = match args {
+            "" => UserId::parse(sender.mxid.as_str()).ok(),
+            _ => parse_user(args, formatted_body),
+        };
+        let user_id = match user_id {
+            Some(user_id) => user_id,
+            None => {
+                room.send(RoomMessageEventContent::text_plain("Invalid command usage! Example: !score or !score @bob:example.org"), None).await.unwrap();
+                return true;
+            }
+        };
+
+        let room_id = room.room_id().to_string();
+        let mut message = MentionMessage::default();
+        message.push_mention(&user_id, &get_display_name(room, &user_id).await);
+        let user = find_user_in_db(&self.conn, user_id.as_str()).filter(|_| user_id != self.bot_user_id);
+        let (user, room_data) = match user.and_then(|user| find_user_room_data_by_user_id_and_room_id(&self.conn, user.id, &room_id).ok().map(|room_data| (user, room_data))) {
+            Some(found) => found,
+            None => {
+                message.push(" has no score in this room", " has no score in this room");
+                message.send(room, &[]).await;
+                return true;
+            }
+        };
+
+        let mut text = format!(" has {} social credit", room_data.social_credit);
+        let ranks = find_all_ranks_for_room_in_db(&self.conn, &room_id).unwrap_or_default();
+        if let Some(rank) = get_rank(&ranks, room_data.social_credit) {
+            text.push_str(&format!(", rank: {}", rank.title));
+        }
+        if let Some(totals) = find_reaction_totals_in_db(&self.conn, user.id, room_data.id, &room_id) {
+            text.push_str(&format!(
+                "\nReceived: {:+} from {} reactions, given: {:+} with {} reactions",
+                totals.received_sum, totals.received_count, totals.given_sum, totals.given_count,
+            ));
+        }
+
+        // The limits can differ by the sign of the emoji, so both are checked and only shown separately if they differ
+        let settings = find_room_settings_in_db(&self.conn, &room_id);
+        let cooldown_config = CooldownConfig::from_settings(&settings, self.reaction_limit, self.reaction_period_minutes);
+        let now = SystemTime::now();
+        let history_start = now.checked_sub(cooldown_config.history_span()).unwrap_or(SystemTime::UNIX_EPOCH);
+        let history = find_reaction_transactions_by_sender_in_db(&self.conn, user.id, &room_id, history_start).unwrap_or_default();
+        let positive = cooldown_config.get_time_till_user_can_react(&history, now, 1);
+        let negative = cooldown_config.get_time_till_user_can_react(&history, now, -1);
+        let format_cooldown = |secs: i64| if secs > 0 { format!("on cooldown for {}m {}s", secs / 60, secs % 60) } else { String::from("can react") };
+        if positive == negative {
+            text.push_str(&format!("\nCooldown: {}", format_cooldown(positive)));
+        }
+        else {
+            text.push_str(&format!("\nCooldown: {} with positive emojis, {} with negative emojis", format_cooldown(positive), format_cooldown(negative)));
+        }
+        if let Some(budget_config) = BudgetConfig::from_settings(&settings) {
+            text.push_str(&format!(", {} of the daily budget left", budget_config.get_remaining_budget(&self.conn, room_data.id, now)));
+        }
+
+        message.push(&text, &escape_html(&text).replace('\n', "<br>"));
+        message.send(room, &[]).await;
+        true
     }
 
     async fn handle_list_emojis(&self, room: &Joined, stripped_body: &mut String) -> bool {
@@ -272,9 +606,22 @@ impl EventHandler {
     async fn handle_help(&self, room: &Joined, stripped_body: &mut String) -> bool {
         if stripped_body == "!help" {
             let help_body = "<h3>Commands:</h3><br>
-                - <b>!list</b>: List all users and their social credit score for the current room<br><br>
+                - <b>!list</b> [page]: List all users and their social credit score for the current room, page by page<br><br>
+                - <b>!score</b> [user]: Show the score, rank, reactions received and given and the cooldown of a user, default: yourself<br><br>
+                - <b>!top</b> [n] and <b>!bottom</b> [n]: List the n users with the highest or lowest score, default: 10<br><br>
+                - <b>!rank</b> [user]: Show the position, score and percentile of a user and the gap to the next higher score, default: yourself<br><br>
                 - <b>!list_emoji</b>: List all registered emojis and their social credit score for the current room<br><br>
-                - <b>!register_emoji</b> <emoji> <social_credit>: Register an emoji with a social credit score for the current room. Example: !register_emoji 😑 -25
+                - <b>!register_emoji</b> <emoji> <social_credit>: Register an emoji with a social credit score for the current room. Example: !register_emoji 😑 -25<br><br>
+                - <b>!export</b> [json|csv] [all]: Upload an export of the users, scores, emojis and reaction history of the current room or of all rooms (admin only)<br><br>
+                - <b>!backup</b>: Create a backup of the database now (admin only)<br><br>
+                - <b>!config</b> [setting] [value|default]: Show the settings of the current room like the cooldown, or change one (admin only). Example: !config cooldown_strategy bucket<br><br>
+                - <b>!ranks</b>: List the ranks of the current room. <b>!ranks add</b> <range> <title> and <b>!ranks remove</b> <title> change them (admin only). Example: !ranks add >=1000 Model Citizen or !ranks add 0..1000 Citizen<br><br>
+                - <b>!consequences</b>: List the consequences of the current room. <b>!consequences add</b> below|above <score> power_level <level> [minutes], mute [minutes] or kick and <b>!consequences remove</b> <id> change them (admin only). Example: !consequences add below -500 mute 60<br><br>
+                - <b>!suspicious</b>: List users that look like they farm each other's score with reactions (admin only)<br><br>
+                - <b>!budget</b>: Show how much social credit you can still hand out today if the room uses the budget cooldown strategy<br><br>
+                - <b>!give</b> <user> <amount> [reason]: Give some of your own social credit to another user, also available as <b>!transfer</b>. Example: !give @bob:example.org 20 for the review<br><br>
+                - <b>!set</b> <user> <score> <reason> and <b>!adjust</b> <user> <change> <reason>: Correct the score of a user, the reason is recorded in the history (admin only). Example: !adjust @bob:example.org -100 reverting reaction spam<br><br>
+                - <b>!season list</b> and <b>!season show</b> <number>: Show the results of past seasons. <b>!season end</b> archives the standings, announces the winners and resets every score (admin only)
             ".to_string();
             let content = RoomMessageEventContent::text_html(help_body.clone(), help_body);
             room.send(content, None).await.unwrap();
@@ -344,22 +691,596 @@ impl EventHandler {
         false
     }
 
-    /// Update the user in the cache and the database, also updates the room data in the database
-    /// if the user has room_data
-    fn update_user_in_db(&self, user: &User) {
-        if user.room_data.is_some() {
-            if update_user_room_data(&self.conn, &user.clone().room_data.unwrap()).is_err() {
-                println!("Unable to update user room data in db"); // error level
+    async fn handle_export(&self, room: &Joined, sender: &User, body: &str) -> bool {
+        if body != "!export" && !body.starts_with("!export ") {
+            return false;
+        }
+
+        if !matches!(sender.user_type, UserType::Admin) {
+            room.send(RoomMessageEventContent::text_plain("You are not allowed to use this command"), None).await.unwrap();
+            return true;
+        }
+
+        let error_message = "Invalid command usage! Example: !export csv or !export json all";
+        let mut format = ExportFormat::Json;
+        let mut all_rooms = false;
+        for part in body.split(' ').skip(1).filter(|part| !part.is_empty()) {
+            match (part, ExportFormat::parse(part)) {
+                ("all", _) => all_rooms = true,
+                (_, Some(parsed_format)) => format = parsed_format,
+                _ => {
+                    room.send(RoomMessageEventContent::text_plain(error_message), None).await.unwrap();
+                    return true;
+                }
+            }
+        }
+
+        let room_id = room.room_id().to_string();
+        let files = {
+            let connection = self.conn.lock().unwrap();
+            export_data(&connection, if all_rooms { None } else { Some(&room_id) })
+                .map_err(|e| e.to_string())
+                .and_then(|data| serialize_export_data(&data, format))
+        };
+        let files = match files {
+            Ok(files) => files,
+            Err(e) => {
+                println!("Unable to export data: {}", e); // error level
+                room.send(RoomMessageEventContent::text_plain("Export failed"), None).await.unwrap();
+                return true;
+            }
+        };
+
+        let mime_type = match format {
+            ExportFormat::Json => mime::APPLICATION_JSON,
+            ExportFormat::Csv => mime::TEXT_CSV,
+        };
+        for (name, content) in files {
+            if let Err(e) = room.send_attachment(&name, &mime_type, &content, AttachmentConfig::new()).await {
+                println!("Unable to upload export {}: {}", name, e); // error level
+                room.send(RoomMessageEventContent::text_plain("Export upload failed"), None).await.unwrap();
+                return true;
+            }
+        }
+        true
+    }
+
+    async fn handle_backup(&self, room: &Joined, sender: &User, body: &str) -> bool {
+        if body != "!backup" {
+            return false;
+        }
+
+        if !matches!(sender.user_type, UserType::Admin) {
+            room.send(RoomMessageEventContent::text_plain("You are not allowed to use this command"), None).await.unwrap();
+            return true;
+        }
+
+        let backup_config = match &self.backup_config {
+            Some(backup_config) => backup_config.clone(),
+            None => {
+                room.send(RoomMessageEventContent::text_plain("Backups are not configured, set BACKUP_DIR to enable them"), None).await.unwrap();
+                return true;
+            }
+        };
+
+        // The backup runs in the background so other events are handled in the meantime
+        let db_path = self.db_path.clone();
+        let room = room.clone();
+        tokio::spawn(async move {
+            let text = match create_backup(&db_path, &backup_config).await {
+                Ok(path) => format!("Backup created: {}", path.file_name().map_or(String::new(), |name| name.to_string_lossy().to_string())),
+                Err(e) => {
+                    println!("Backup failed: {}", e); // error level
+                    String::from("Backup failed")
+                }
+            };
+            room.send(RoomMessageEventContent::text_plain(text), None).await.unwrap();
+        });
+        true
+    }
+
+    async fn handle_config(&self, room: &Joined, sender: &User, body: &str) -> bool {
+        if body != "!config" && !body.starts_with("!config ") {
+            return false;
+        }
+
+        let room_id = room.room_id().to_string();
+        let parts: Vec<&str> = body.split(' ').skip(1).filter(|part| !part.is_empty()).collect();
+        if parts.is_empty() {
+            let answer = get_room_config_answer(&self.conn, &room_id);
+            room.send(RoomMessageEventContent::text_html(answer.text, answer.html), None).await.unwrap();
+            return true;
+        }
+
+        if !matches!(sender.user_type, UserType::Admin) {
+            room.send(RoomMessageEventContent::text_plain("You are not allowed to use this command"), None).await.unwrap();
+            return true;
+        }
+
+        let error_message = "Invalid command usage! Example: !config reaction_limit 5 or !config reaction_limit default";
+        let (setting, value) = match (parts.as_slice(), parts.first().and_then(|key| find_room_setting(key))) {
+            ([_, value], Some(setting)) => (setting, *value),
+            _ => {
+                room.send(RoomMessageEventContent::text_plain(error_message), None).await.unwrap();
+                return true;
+            }
+        };
+
+        let (result, text) = if value == "default" {
+            (delete_room_setting(&self.conn, &room_id, setting.key), format!("{} reset to the default", setting.key))
+        }
+        else if setting.is_valid(value) {
+            (set_room_setting(&self.conn, &room_id, setting.key, value), format!("{} set to {}", setting.key, value))
+        }
+        else {
+            room.send(RoomMessageEventContent::text_plain(format!("Invalid value for {}, expected: {}", setting.key, setting.description)), None).await.unwrap();
+            return true;
+        };
+
+        if let Err(e) = result {
+            println!("Unable to change room setting {}: {}", setting.key, e); // error level
+            room.send(RoomMessageEventContent::text_plain("Unable to change the setting"), None).await.unwrap();
+            return true;
+        }
+        room.send(RoomMessageEventContent::text_plain(text), None).await.unwrap();
+        true
+    }
+
+    async fn handle_ranks(&self, room: &Joined, sender: &User, body: &str) -> bool {
+        if body != "!ranks" && !body.starts_with("!ranks ") {
+            return false;
+        }
+
+        let room_id = room.room_id().to_string();
+        let parts: Vec<&str> = body.split(' ').skip(1).filter(|part| !part.is_empty()).collect();
+        if parts.is_empty() {
+            let answer = get_rank_list_answer(&self.conn, &room_id);
+            room.send(RoomMessageEventContent::text_html(answer.text, answer.html), None).await.unwrap();
+            return true;
+        }
+
+        if !matches!(sender.user_type, UserType::Admin) {
+            room.send(RoomMessageEventContent::text_plain("You are not allowed to use this command"), None).await.unwrap();
+            return true;
+        }
+
+        let error_message = "Invalid command usage! Example: !ranks add <0 Enemy of the State or !ranks remove Enemy of the State";
+        let text = match parts.as_slice() {
+            ["add", range, title @ ..] if !title.is_empty() => {
+                let (min_social_credit, max_social_credit) = match parse_rank_range(range) {
+                    Some(range) => range,
+                    None => {
+                        room.send(RoomMessageEventContent::text_plain("Invalid range, expected >=N, >N, <N, <=N or N..M"), None).await.unwrap();
+                        return true;
+                    }
+                };
+                let rank = Rank {
+                    id: 0,
+                    room_id,
+                    title: title.join(" "),
+                    min_social_credit,
+                    max_social_credit,
+                };
+                match insert_rank(&self.conn, &rank) {
+                    Ok(_) => format!("Added rank {}", rank.title),
+                    Err(e) => {
+                        println!("Unable to insert rank into db: {}", e); // error level
+                        String::from("Unable to add the rank")
+                    }
+                }
+            },
+            ["remove", title @ ..] if !title.is_empty() => {
+                let title = title.join(" ");
+                match delete_rank(&self.conn, &room_id, &title) {
+                    Ok(0) => format!("There is no rank {}", title),
+                    Ok(_) => format!("Removed rank {}", title),
+                    Err(e) => {
+                        println!("Unable to delete rank from db: {}", e); // error level
+                        String::from("Unable to remove the rank")
+                    }
+                }
+            },
+            _ => String::from(error_message),
+        };
+        room.send(RoomMessageEventContent::text_plain(text), None).await.unwrap();
+        true
+    }
+
+    async fn handle_consequences(&self, room: &Joined, sender: &User, body: &str) -> bool {
+        if body != "!consequences" && !body.starts_with("!consequences ") {
+            return false;
+        }
+
+        let room_id = room.room_id().to_string();
+        let parts: Vec<&str> = body.split(' ').skip(1).filter(|part| !part.is_empty()).collect();
+        if parts.is_empty() {
+            let answer = get_consequence_list_answer(&self.conn, &room_id);
+            room.send(RoomMessageEventContent::text_html(answer.text, answer.html), None).await.unwrap();
+            return true;
+        }
+
+        if !matches!(sender.user_type, UserType::Admin) {
+            room.send(RoomMessageEventContent::text_plain("You are not allowed to use this command"), None).await.unwrap();
+            return true;
+        }
+
+        let error_message = "Invalid command usage! Example: !consequences add below -500 mute 60, !consequences add below -1000 kick, !consequences add above 2000 power_level 10 or !consequences remove 1";
+        let text = match parts.as_slice() {
+            ["add", args @ ..] => match parse_consequence(&room_id, args) {
+                Some(consequence) => match insert_consequence(&self.conn, &consequence) {
+                    Ok(id) => format!("Added consequence {}: {}", id, format_consequence(&consequence)),
+                    Err(e) => {
+                        println!("Unable to insert consequence into db: {}", e); // error level
+                        String::from("Unable to add the consequence")
+                    }
+                },
+                None => String::from(error_message),
+            },
+            ["remove", id] => match id.parse::<i32>().ok().and_then(|id| find_consequence_in_db(&self.conn, &room_id, id)) {
+                Some(consequence) => {
+                    revert_all_applied_consequences(&self.conn, room, &self.bot_user_id, &consequence).await;
+                    match delete_consequence(&self.conn, &room_id, consequence.id) {
+                        Ok(_) => format!("Removed consequence {}", consequence.id),
+                        Err(e) => {
+                            println!("Unable to delete consequence from db: {}", e); // error level
+                            String::from("Unable to remove the consequence")
+                        }
+                    }
+                },
+                None => format!("There is no consequence {}", id),
+            },
+            _ => String::from(error_message),
+        };
+        room.send(RoomMessageEventContent::text_plain(text), None).await.unwrap();
+        true
+    }
+
+    async fn handle_suspicious(&self, room: &Joined, sender: &User, body: &str) -> bool {
+        if body != "!suspicious" {
+            return false;
+        }
+
+        if !matches!(sender.user_type, UserType::Admin) {
+            room.send(RoomMessageEventContent::text_plain("You are not allowed to use this command"), None).await.unwrap();
+            return true;
+        }
+
+        let room_id = room.room_id().to_string();
+        let config = CollusionConfig::from_settings(&find_room_settings_in_db(&self.conn, &room_id));
+        get_suspicious_answer(&self.conn, room, config.as_ref()).await.send(room, &[]).await;
+        true
+    }
+
+    async fn handle_budget(&self, room: &Joined, sender: &User, body: &str) -> bool {
+        if body != "!budget" {
+            return false;
+        }
+
+        let config = match BudgetConfig::from_settings(&find_room_settings_in_db(&self.conn, room.room_id().as_str())) {
+            Some(config) => config,
+            None => {
+                room.send(RoomMessageEventContent::text_plain("This room does not use budgets, reactions are limited by the cooldown"), None).await.unwrap();
+                return true;
+            }
+        };
+        let (sender_room_data, sender_id) = match (&sender.room_data, UserId::parse(sender.mxid.as_str())) {
+            (Some(room_data), Ok(sender_id)) => (room_data, sender_id),
+            _ => {
+                println!("Sender of !budget does not have room data"); // error level
+                return true;
+            }
+        };
+
+        let now = SystemTime::now();
+        let remaining = config.get_remaining_budget(&self.conn, sender_room_data.id, now);
+        let mut message = MentionMessage::default();
+        message.push_mention(&sender_id, &get_display_name(room, &sender_id).await);
+        let text = format!(", {} of your daily budget of {} is left, it resets in {}", remaining, config.daily_budget, format_duration(config.get_time_till_reset(now)));
+        message.push(&text, &escape_html(&text));
+        message.send(room, &[]).await;
+        true
+    }
+
+    /// Moves social credit from the sender to another user, the recipient is the first pill of the message
+    /// or a matrix id right after the command
+    async fn handle_give(&self, room: &Joined, sender: &User, body: &str, formatted_body: Option<&str>) -> bool {
+        let args = match body.strip_prefix("!give ").or_else(|| body.strip_prefix("!transfer ")) {
+            Some(args) => args,
+            None => return false,
+        };
+
+        let error_message = "Invalid command usage! Example: !give @bob:example.org 20 for the review";
+        let (recipient_id, amount, reason) = match parse_user_and_number(args, formatted_body).filter(|(_, amount, _)| *amount > 0) {
+            Some(parsed) => parsed,
+            None => {
+                room.send(RoomMessageEventContent::text_plain(error_message), None).await.unwrap();
+                return true;
+            }
+        };
+
+        if recipient_id.as_str() == sender.mxid || recipient_id == self.bot_user_id {
+            room.send(RoomMessageEventContent::text_plain("You can not give social credit to yourself or to me"), None).await.unwrap();
+            return true;
+        }
+
+        let room_id = room.room_id().to_string();
+        let recipient = setup_user(&self.conn, Some(&room_id), &recipient_id.to_string(), UserType::Default, self.initial_social_credit);
+        let (sender_room_data, recipient_room_data, sender_id) = match (&sender.room_data, recipient.and_then(|recipient| recipient.room_data), UserId::parse(sender.mxid.as_str())) {
+            (Some(sender_room_data), Some(recipient_room_data), Ok(sender_id)) => (sender_room_data, recipient_room_data, sender_id),
+            _ => {
+                println!("Sender or recipient of a transfer does not have room data"); // error level
+                return true;
+            }
+        };
+
+        let settings = find_room_settings_in_db(&self.conn, &room_id);
+        let now = SystemTime::now();
+        let transfer_limit = settings.get("transfer_limit").and_then(|value| value.parse::<i32>().ok()).unwrap_or(DEFAULT_TRANSFER_LIMIT);
+        let history = find_transactions_by_sender_in_db(&self.conn, sender.id, &room_id, "transfer", now - Duration::from_secs(24 * 60 * 60)).unwrap_or_default();
+        let time_till_user_can_transfer = get_time_till_user_can_transfer(&history, sender_room_data.id, now, transfer_limit);
+        if time_till_user_can_transfer > 0 {
+            let text = format!("You already made {} transfers today, you can transfer again in {}", transfer_limit, format_duration(time_till_user_can_transfer));
+            room.send(RoomMessageEventContent::text_plain(text), None).await.unwrap();
+            return true;
+        }
+
+        let recipient_bounds = ScoreBounds::from_settings(&settings).without_max_change();
+        let floor = settings.get("transfer_floor").and_then(|value| value.parse::<i32>().ok()).unwrap_or(0).max(recipient_bounds.min);
+        let sender_bounds = ScoreBounds { min: floor, ..recipient_bounds.without_max_change() };
+        let result = transfer_social_credit(&self.conn, sender_room_data.id, recipient_room_data.id, amount, &sender_bounds, &recipient_bounds, reason.as_deref());
+        let (sender_social_credit, recipient_social_credit) = match result {
+            Ok(TransferResult::Done(sender_social_credit, recipient_social_credit)) => (sender_social_credit, recipient_social_credit),
+            Ok(TransferResult::OutOfBounds) => {
+                let text = format!("The transfer is not possible, your score can not go below {} and scores can not go above {}", floor, recipient_bounds.max);
+                room.send(RoomMessageEventContent::text_plain(text), None).await.unwrap();
+                return true;
+            },
+            Err(e) => {
+                println!("Unable to transfer social credit: {}", e); // error level
+                room.send(RoomMessageEventContent::text_plain("Unable to transfer the social credit"), None).await.unwrap();
+                return true;
+            }
+        };
+
+        let mut names = [
+            (sender_id.to_string(), get_display_name(room, &sender_id).await),
+            (recipient_id.to_string(), get_display_name(room, &recipient_id).await),
+        ];
+        disambiguate_names(&mut names);
+        let [(_, sender_name), (_, recipient_name)] = names;
+        let mut message = MentionMessage::default();
+        message.push_mention(&sender_id, &sender_name);
+        message.push(&format!(" gave {} Social Credit to ", amount), &format!(" gave <b>{}</b> Social Credit to ", amount));
+        message.push_mention(&recipient_id, &recipient_name);
+        if let Some(reason) = &reason {
+            message.push(&format!(" for {}", reason), &format!(" for {}", escape_html(reason)));
+        }
+        message.push(
+            &format!(", new scores: {} and {}", sender_social_credit, recipient_social_credit),
+            &format!(", new scores: <b>{}</b> and <b>{}</b>", sender_social_credit, recipient_social_credit),
+        );
+        let ranks = find_all_ranks_for_room_in_db(&self.conn, &room_id).unwrap_or_default();
+        push_rank_change(&mut message, &ranks, &sender_id, &sender_name, sender_room_data.social_credit, sender_social_credit);
+        push_rank_change(&mut message, &ranks, &recipient_id, &recipient_name, recipient_room_data.social_credit, recipient_social_credit);
+        let ping = get_switch_setting(&settings, "ping_changes", true);
+        let ping_user_ids: Vec<&UserId> = if ping { vec![&recipient_id] } else { Vec::new() };
+        message.send(room, &ping_user_ids).await;
+
+        evaluate_consequences(&self.conn, room, &self.bot_user_id, &sender_id, sender_social_credit).await;
+        evaluate_consequences(&self.conn, room, &self.bot_user_id, &recipient_id, recipient_social_credit).await;
+        true
+    }
+
+    /// Handles !set and !adjust, admins can correct a score by hand. The change is recorded as an admin transaction with the reason
+    async fn handle_score_correction(&self, room: &Joined, sender: &User, body: &str, formatted_body: Option<&str>) -> bool {
+        let (is_set, args) = match (body.strip_prefix("!set "), body.strip_prefix("!adjust ")) {
+            (Some(args), _) => (true, args),
+            (_, Some(args)) => (false, args),
+            _ => return false,
+        };
+
+        if !matches!(sender.user_type, UserType::Admin) {
+            room.send(RoomMessageEventContent::text_plain("You are not allowed to use this command"), None).await.unwrap();
+            return true;
+        }
+
+        let sender_id = match UserId::parse(sender.mxid.as_str()) {
+            Ok(sender_id) => sender_id,
+            Err(e) => {
+                println!("Sender of a score correction has an invalid matrix id: {}", e); // error level
+                return true;
+            }
+        };
+
+        let error_message = "Invalid command usage! Example: !set @bob:example.org 500 reason or !adjust @bob:example.org -100 reason, the reason is required";
+        let (user_id, number, reason) = match parse_user_and_number(args, formatted_body) {
+            Some((user_id, number, Some(reason))) => (user_id, number, reason),
+            _ => {
+                room.send(RoomMessageEventContent::text_plain(error_message), None).await.unwrap();
+                return true;
+            }
+        };
+
+        let room_id = room.room_id().to_string();
+        let room_data = match setup_user(&self.conn, Some(&room_id), &user_id.to_string(), UserType::Default, self.initial_social_credit).and_then(|user| user.room_data) {
+            Some(room_data) => room_data,
+            None => {
+                println!("User of a score correction does not have room data"); // error level
+                return true;
             }
+        };
+
+        let bounds = ScoreBounds::from_settings(&find_room_settings_in_db(&self.conn, &room_id)).without_max_change();
+        let record = Transaction::new(room_data.id, Some(sender.id), "admin", 0, 0, Some(reason.clone()));
+        let result = change_social_credit(&self.conn, &record, &bounds, |social_credit| {
+            let delta = if is_set { i32::try_from(number as i64 - social_credit as i64).ok()? } else { number };
+            Some(delta).filter(|delta| !bounds.apply_change(social_credit, *delta).clamped)
+        });
+        let (old_social_credit, new_social_credit) = match result {
+            Ok(Some((old_social_credit, score_change))) => (old_social_credit, score_change.new_social_credit),
+            Ok(None) => {
+                let text = format!("The score has to stay between {} and {}", bounds.min, bounds.max);
+                room.send(RoomMessageEventContent::text_plain(text), None).await.unwrap();
+                return true;
+            },
+            Err(e) => {
+                println!("Unable to correct the score of {}: {}", user_id, e); // error level
+                room.send(RoomMessageEventContent::text_plain("Unable to change the score"), None).await.unwrap();
+                return true;
+            }
+        };
+        println!("{} changed the score of {} in room {} from {} to {}: {}", sender.mxid, user_id, room_id, old_social_credit, new_social_credit, reason); // debug level
+
+        let mut names = [
+            (sender_id.to_string(), get_display_name(room, &sender_id).await),
+            (user_id.to_string(), get_display_name(room, &user_id).await),
+        ];
+        disambiguate_names(&mut names);
+        let [(_, sender_name), (_, name)] = names;
+        let mut message = MentionMessage::default();
+        message.push_mention(&sender_id, &sender_name);
+        message.push(" changed the Social Credit Score of ", " changed the Social Credit Score of ");
+        message.push_mention(&user_id, &name);
+        message.push(
+            &format!(" from {} to {}, reason: {}", old_social_credit, new_social_credit, reason),
+            &format!(" from <b>{}</b> to <b>{}</b>, reason: {}", old_social_credit, new_social_credit, escape_html(&reason)),
+        );
+        let ranks = find_all_ranks_for_room_in_db(&self.conn, &room_id).unwrap_or_default();
+        push_rank_change(&mut message, &ranks, &user_id, &name, old_social_credit, new_social_credit);
+        message.send(room, &[]).await;
+
+        evaluate_consequences(&self.conn, room, &self.bot_user_id, &user_id, new_social_credit).await;
+        true
+    }
+
+    async fn handle_season(&self, room: &Joined, sender: &User, body: &str) -> bool {
+        if body != "!season" && !body.starts_with("!season ") {
+            return false;
         }
 
-        if update_user(&self.conn, &user).is_err() {
-            println!("Unable to update user in db"); // error level
+        let room_id = room.room_id().to_string();
+        let parts: Vec<&str> = body.split(' ').skip(1).filter(|part| !part.is_empty()).collect();
+        match parts.as_slice() {
+            ["list"] => {
+                let answer = get_season_list_answer(&self.conn, &room_id);
+                room.send(RoomMessageEventContent::text_html(answer.text, answer.html), None).await.unwrap();
+            },
+            ["show", number] => {
+                let season = number.parse::<i32>().ok().and_then(|number| find_season_in_db(&self.conn, &room_id, number));
+                match season {
+                    Some(season) => {
+                        let standings = find_season_standings_in_db(&self.conn, season.id).unwrap_or_default();
+                        let heading = format!("Final standings of season {}, ended {}:", season.number, format_time(season.ended_at));
+                        get_season_standings_message(room, &heading, &standings, None).await.send(room, &[]).await;
+                    },
+                    None => {
+                        room.send(RoomMessageEventContent::text_plain(format!("There is no season {}", number)), None).await.unwrap();
+                    }
+                }
+            },
+            ["end"] => {
+                if !matches!(sender.user_type, UserType::Admin) {
+                    room.send(RoomMessageEventContent::text_plain("You are not allowed to use this command"), None).await.unwrap();
+                    return true;
+                }
+
+                let season = end_season(&self.conn, &room_id, self.bot_user_id.as_str(), self.initial_social_credit)
+                    .map(|number| find_season_in_db(&self.conn, &room_id, number));
+                let season = match season {
+                    Ok(Some(season)) => season,
+                    Ok(None) | Err(_) => {
+                        println!("Unable to end the season in room {}: {:?}", room_id, season.err()); // error level
+                        room.send(RoomMessageEventContent::text_plain("Unable to end the season"), None).await.unwrap();
+                        return true;
+                    }
+                };
+                println!("{} ended season {} in room {}", sender.mxid, season.number, room_id); // debug level
+
+                let top_count = find_room_settings_in_db(&self.conn, &room_id).get("season_top").and_then(|value| value.parse::<usize>().ok()).unwrap_or(DEFAULT_SEASON_TOP);
+                let standings = find_season_standings_in_db(&self.conn, season.id).unwrap_or_default();
+                let heading = format!("Season {} has ended! The final top {}:", season.number, top_count);
+                let mut message = get_season_standings_message(room, &heading, &standings, Some(top_count)).await;
+                let text = format!("Every score was reset to {}, good luck in season {}", self.initial_social_credit, season.number + 1);
+                message.push(&format!("\n{}", text), &format!("<br>{}", text));
+                message.send(room, &[]).await;
+            },
+            _ => {
+                room.send(RoomMessageEventContent::text_plain("Invalid command usage! Example: !season list, !season show 1 or !season end"), None).await.unwrap();
+            },
         }
+        true
+    }
+}
+
+/// The user is either written as a matrix id or the first pill of the message
+fn parse_user(args: &str, formatted_body: Option<&str>) -> Option<OwnedUserId> {
+    match args.split(' ').next().map(UserId::parse) {
+        Some(Ok(user_id)) => Some(user_id),
+        _ => formatted_body.and_then(find_mentioned_user_id),
     }
+}
+
+/// Parses "<user> <number> [for] [reason]", the user is a matrix id right after the command or the first pill of the message.
+/// With a pill the arguments are read from the html after the pill, as the display name in the body can contain spaces and numbers
+fn parse_user_and_number(args: &str, formatted_body: Option<&str>) -> Option<(OwnedUserId, i32, Option<String>)> {
+    let (first, rest) = args.trim_start().split_once(' ').unwrap_or((args.trim_start(), ""));
+    let (user_id, rest) = match UserId::parse(first) {
+        Ok(user_id) => (user_id, rest.to_string()),
+        Err(_) => find_mentioned_user_id_and_following_text(formatted_body?)?,
+    };
+    let parts: Vec<&str> = rest.split_whitespace().collect();
+    let number = parts.first()?.parse::<i32>().ok()?;
 
-    fn is_user_the_bot(&self, name: &str, url: &str) -> bool {
-        name == self.bot_username && url == self.homeserver_url_without_protocol
+    let mut reason_parts = &parts[1..];
+    if reason_parts.first() == Some(&"for") {
+        reason_parts = &reason_parts[1..];
     }
+    let reason = Some(reason_parts.join(" ")).filter(|reason| !reason.is_empty());
+    Some((user_id, number, reason))
+}
+
+/// The time the event was sent according to its homeserver, capped at the current time
+/// so a homeserver with a clock in the future cannot put reactions in the future
+fn get_event_time(event: &AnySyncMessageLikeEvent) -> SystemTime {
+    let now = SystemTime::now();
+    event.origin_server_ts().to_system_time().map_or(now, |time| time.min(now))
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bob() -> OwnedUserId {
+        UserId::parse("@bob:example.org").unwrap()
+    }
+
+    #[test]
+    fn parse_user_and_number_with_matrix_id() {
+        assert_eq!(parse_user_and_number("@bob:example.org 20 for review", None), Some((bob(), 20, Some(String::from("review")))));
+        assert_eq!(parse_user_and_number(" @bob:example.org  -5  spam ", None), Some((bob(), -5, Some(String::from("spam")))));
+        assert_eq!(parse_user_and_number("@bob:example.org 20", None), Some((bob(), 20, None)));
+        assert_eq!(parse_user_and_number("@bob:example.org 20 for", None), Some((bob(), 20, None)));
+    }
+
+    #[test]
+    fn parse_user_and_number_with_pill() {
+        // The display name of the pill contains a number that must not be taken as the amount
+        let formatted_body = "!give <a href=\"https://matrix.to/#/@bob:example.org\">Bob 2</a> 20 for review";
+        assert_eq!(parse_user_and_number("Bob 2 20 for review", Some(formatted_body)), Some((bob(), 20, Some(String::from("review")))));
+
+        let formatted_body = "!adjust <a href='https://matrix.to/#/%40bob%3Aexample.org'>Bob</a> -3 <b>fish</b> &amp; chips";
+        assert_eq!(parse_user_and_number("Bob -3 fish & chips", Some(formatted_body)), Some((bob(), -3, Some(String::from("fish & chips")))));
+    }
+
+    #[test]
+    fn parse_user_and_number_invalid() {
+        assert_eq!(parse_user_and_number("", None), None);
+        assert_eq!(parse_user_and_number("@bob:example.org", None), None);
+        assert_eq!(parse_user_and_number("@bob:example.org many", None), None);
+        assert_eq!(parse_user_and_number("@bob:example.org 99999999999", None), None);
+        assert_eq!(parse_user_and_number("Bob 20", None), None);
+        assert_eq!(parse_user_and_number("Bob 20", Some("!give Bob 20")), None);
+        let formatted_body = "!give <a href=\"https://matrix.to/#/@bob:example.org\">Bob 2</a> for review";
+        assert_eq!(parse_user_and_number("Bob 2 for review", Some(formatted_body)), None);
+    }
+}
@@ -1,23 +1,30 @@
+mod catch_up;
+mod cli;
+mod config;
 mod event_handler;
 mod data;
 mod utils;
+mod web;
 
 use std::env;
 use matrix_sdk::{
-    Client, config::SyncSettings,
+    Client, config::SyncSettings, LoopCtrl,
 };
 use matrix_sdk::room::Room;
 use matrix_sdk::ruma::events::AnySyncMessageLikeEvent;
 use std::sync::{Arc, Mutex};
-use rusqlite::{Connection};
-use crate::data::emoji::create_table_emoji;
-use crate::data::event::create_table_event;
-use crate::data::user::create_table_user;
-use crate::data::user_room_data::create_table_user_room_data;
-use crate::data::user_reaction::{create_table_user_reaction};
+use crate::catch_up::catch_up;
+use crate::config::Config;
+use crate::data::bot_state::{get_sync_token, set_sync_token};
+use crate::data::open_database;
 use crate::event_handler::EventHandler;
 use crate::utils::autojoin::on_stripped_state_member;
+use crate::utils::backup_util::run_backup_schedule;
+use crate::utils::consequence_util::run_consequence_schedule;
+use crate::utils::decay_util::run_decay_schedule;
+use crate::utils::maintenance_util::run_maintenance_schedule;
 use crate::utils::user_util::{initial_admin_user_setup};
+use crate::web::run_dashboard;
 
 
 // todo session preservation and emoji verification
@@ -26,50 +33,64 @@ use crate::utils::user_util::{initial_admin_user_setup};
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    let db_path = env::var("DB_PATH").expect("DB_PATH not set");
-    let initial_social_credit = get_env_var_as_i32("INITIAL_SOCIAL_CREDIT");
-    let reaction_timespan = get_env_var_as_i32("REACTION_TIMESPAN");
-    let reaction_limit = get_env_var_as_i32("REACTION_LIMIT");
-    let admin_username = env::var("ADMIN_USERNAME").expect("ADMIN_USERNAME not set");
-    let username = env::var("MATRIX_USERNAME").expect("MATRIX_USERNAME not set");
-    let homeserver_url = env::var("MATRIX_HOMESERVER_URL").expect("MATRIX_HOMESERVER_URL not set");
-    let homeserver_url_relative : &str;
-    if homeserver_url.starts_with("https://") {
-        homeserver_url_relative = homeserver_url.strip_prefix("https://").expect("Failed to strip https:// from homeserver url");
+    let args: Vec<String> = env::args().skip(1).collect();
+    match args.first().map(|command| command.as_str()) {
+        None | Some("serve") => serve().await,
+        Some(_) => cli::run(&args),
     }
-    else if homeserver_url.starts_with("http://") {
-        homeserver_url_relative = homeserver_url.strip_prefix("http://").expect("Failed to strip http:// from homeserver url");
-    }
-    else {
+}
+
+/// Starts the bot and runs the sync loop
+async fn serve() -> anyhow::Result<()> {
+    let config = Config::from_env();
+    if !config.homeserver_url.starts_with("https://") && !config.homeserver_url.starts_with("http://") {
         panic!("Invalid homeserver url");
     }
-    let password = env::var("MATRIX_PASSWORD").expect("MATRIX_PASSWORD not set");
 
     // Database setup
-    let conn = Connection::open(db_path)?;
-    conn.execute("PRAGMA foreign_keys = ON", []).expect("Failed to enable foreign key support");
-    create_table_user(&conn);
-    create_table_user_room_data(&conn);
-    create_table_user_reaction(&conn);
-    create_table_emoji(&conn);
-    create_table_event(&conn);
-
-    let client = Client::builder().homeserver_url(homeserver_url.clone()).build().await?;
-    client.login_username(username.as_str(), &*password).initial_device_display_name("Social Credit System").send().await?;
+    let conn = open_database(&config.db_path);
+
+    let client = Client::builder().homeserver_url(config.homeserver_url.clone()).build().await?;
+    client.login_username(config.username.as_str(), &config.password).initial_device_display_name("Social Credit System").send().await?;
     client.add_event_handler(on_stripped_state_member);
 
+    // The server name of the user id can differ from the homeserver url, for example with .well-known delegation
+    let bot_user_id = client.user_id().expect("No user id after login").to_owned();
+    println!("Logged in as {}", bot_user_id);
+
     let shared_conn = Arc::new(Mutex::new(conn));
-    let event_handler = Arc::new(EventHandler::new(
-        shared_conn.clone(),
-        username,
-        homeserver_url.clone(),
-        initial_social_credit,
-        reaction_timespan,
-        reaction_limit,
-    ));
+    let event_handler = Arc::new(EventHandler::new(shared_conn.clone(), &config, bot_user_id.clone()));
+
+    if config.dashboard_enabled {
+        tokio::spawn(run_dashboard(shared_conn.clone(), config.dashboard_address, bot_user_id.to_string()));
+    }
+
+    if let Some(backup_config) = config.backup.clone() {
+        tokio::spawn(run_backup_schedule(config.db_path.clone(), backup_config));
+    }
+
+    tokio::spawn(run_maintenance_schedule(shared_conn.clone(), config.db_path.clone(), config.maintenance.clone()));
+    tokio::spawn(run_decay_schedule(shared_conn.clone(), config.initial_social_credit));
+    tokio::spawn(run_consequence_schedule(client.clone(), shared_conn.clone()));
 
-    initial_admin_user_setup(&shared_conn, &admin_username, &homeserver_url_relative);
+    initial_admin_user_setup(&shared_conn, &config.admin_username, bot_user_id.server_name());
+
+    // Resume from the last processed sync response, so a restart neither replays old events nor misses
+    // events that were sent while the bot was down. The event table still protects against handling
+    // an event twice if the bot stops after handling a response but before the token was stored
+    let sync_settings = match get_sync_token(&shared_conn) {
+        Some(token) => {
+            println!("Catching up with the events since the last stored sync token");
+            let next_batch = catch_up(&client, &event_handler, token, config.maintenance.event_retention_days).await?;
+            if let Err(e) = set_sync_token(&shared_conn, &next_batch) {
+                println!("Unable to store sync token: {}", e); // error level
+            }
+            SyncSettings::default().token(next_batch)
+        },
+        None => SyncSettings::default(),
+    };
 
+    // Registered after the catch up, so missed events are only handled once and without a message per reaction
     client.add_event_handler({
         let event_handler = event_handler.clone();
         move |event: AnySyncMessageLikeEvent, room: Room| {
@@ -80,16 +101,17 @@ async fn main() -> anyhow::Result<()> {
         }
     });
 
-    client.sync(SyncSettings::default()).await.expect("Sync loop fail");
+
+    client.sync_with_callback(sync_settings, |response| {
+        let conn = shared_conn.clone();
+        async move {
+            // The callback runs after all event handlers of this response finished
+            if let Err(e) = set_sync_token(&conn, &response.next_batch) {
+                println!("Unable to store sync token: {}", e); // error level
+            }
+            LoopCtrl::Continue
+        }
+    }).await.expect("Sync loop fail");
 
     Ok(())
 }
-
-fn get_env_var_as_i32(var_name: &str) -> i32 {
-    env::var(var_name)
-        .map_err(|e| format!("Couldn't read {}: {}", var_name, e))
-        .and_then(|value| {
-            value.parse::<i32>().map_err(|e| format!("Failed to parse {}: {}", var_name, e))
-        })
-        .expect(&format!("Failed to parse {}", var_name))
-}
\ No newline at end of file
@@ -0,0 +1,186 @@
+use std::cmp::Reverse;
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use chrono::{Datelike, DateTime, NaiveDateTime, Utc};
+use rusqlite::{Connection, DatabaseName, OpenFlags};
+use rusqlite::backup::Backup;
+use crate::config::BackupConfig;
+
+const BACKUP_FILE_PREFIX: &str = "social_credit-";
+const BACKUP_FILE_SUFFIX: &str = ".db";
+const BACKUP_TIMESTAMP_FORMAT: &str = "%Y%m%d-%H%M%S";
+
+/// Copies the database to path using the online backup api of sqlite,
+/// so it is safe to run while the database is in use
+pub fn backup_database(conn: &Connection, path: &Path) -> Result<(), rusqlite::Error> {
+    conn.backup(DatabaseName::Main, path, None)
+}
+
+/// Uses its own connection and copies the database in small steps with pauses in between,
+/// so the event handler is never blocked for long while the backup runs
+fn backup_database_in_steps(db_path: &str, path: &Path) -> Result<(), rusqlite::Error> {
+    let source = Connection::open_with_flags(db_path, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+    let mut destination = Connection::open(path)?;
+    let backup = Backup::new(&source, &mut destination)?;
+    backup.run_to_completion(100, Duration::from_millis(10), None)
+}
+
+/// Writes a timestamped backup into the backup directory and removes old backups afterwards
+pub async fn create_backup(db_path: &str, config: &BackupConfig) -> Result<PathBuf, String> {
+    let db_path = db_path.to_string();
+    let config = config.clone();
+
+    tokio::task::spawn_blocking(move || {
+        fs::create_dir_all(&config.directory).map_err(|e| e.to_string())?;
+        let file_name = format!("{}{}{}", BACKUP_FILE_PREFIX, Utc::now().format(BACKUP_TIMESTAMP_FORMAT), BACKUP_FILE_SUFFIX);
+        let path = config.directory.join(file_name);
+
+        // Written under a different name first, so an unfinished backup is never mistaken for a complete one
+        let partial_path = path.with_extension("db.partial");
+        if let Err(e) = backup_database_in_steps(&db_path, &partial_path) {
+            let _ = fs::remove_file(&partial_path);
+            return Err(e.to_string());
+        }
+        fs::rename(&partial_path, &path).map_err(|e| e.to_string())?;
+
+        let removed = apply_backup_retention(&config)?;
+        if removed > 0 {
+            println!("Removed {} old backups", removed); // debug level
+        }
+        Ok(path)
+    }).await.map_err(|e| e.to_string())?
+}
+
+/// Returns all backups in the directory, newest first
+fn list_backups(directory: &Path) -> Vec<(DateTime<Utc>, PathBuf)> {
+    let entries = match fs::read_dir(directory) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+
+    let mut backups: Vec<(DateTime<Utc>, PathBuf)> = entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let file_name = entry.file_name().to_string_lossy().to_string();
+            let timestamp = file_name.strip_prefix(BACKUP_FILE_PREFIX)?.strip_suffix(BACKUP_FILE_SUFFIX)?;
+            let time = NaiveDateTime::parse_from_str(timestamp, BACKUP_TIMESTAMP_FORMAT).ok()?;
+            Some((DateTime::from_naive_utc_and_offset(time, Utc), entry.path()))
+        })
+        .collect();
+    backups.sort_by_key(|backup| Reverse(backup.0));
+    backups
+}
+
+/// Keeps the newest backup of each of the last keep_daily days and of each of the last keep_weekly weeks
+/// that have a backup, all other backups are deleted. Returns the number of deleted backups
+fn apply_backup_retention(config: &BackupConfig) -> Result<usize, String> {
+    let backups = list_backups(&config.directory);
+    let outdated = find_outdated_backups(&backups, config.keep_daily, config.keep_weekly);
+    for path in &outdated {
+        fs::remove_file(path).map_err(|e| format!("Unable to remove old backup {}: {}", path.display(), e))?;
+    }
+    Ok(outdated.len())
+}
+
+/// Returns the backups that are not kept, backups have to be sorted newest first.
+/// The newest backup is always kept, even if keep_daily and keep_weekly are both 0
+fn find_outdated_backups(backups: &[(DateTime<Utc>, PathBuf)], keep_daily: usize, keep_weekly: usize) -> Vec<&PathBuf> {
+    let mut keep: HashSet<&PathBuf> = backups.first().map(|(_, path)| path).into_iter().collect();
+
+    let mut days = Vec::new();
+    let mut weeks = Vec::new();
+    for (time, path) in backups {
+        let day = time.date_naive();
+        if !days.contains(&day) && days.len() < keep_daily {
+            days.push(day);
+            keep.insert(path);
+        }
+
+        let week = (time.iso_week().year(), time.iso_week().week());
+        if !weeks.contains(&week) && weeks.len() < keep_weekly {
+            weeks.push(week);
+            keep.insert(path);
+        }
+    }
+
+    backups.iter().map(|(_, path)| path).filter(|path| !keep.contains(path)).collect()
+}
+
+/// Creates a backup every interval_hours, counted from the newest backup in the directory
+/// so the schedule is kept across restarts
+pub async fn run_backup_schedule(db_path: String, config: BackupConfig) {
+    let interval = chrono::Duration::hours(config.interval_hours.max(1) as i64);
+    loop {
+        let wait = match list_backups(&config.directory).first() {
+            Some((last_backup, _)) => (*last_backup + interval - Utc::now()).to_std().unwrap_or(Duration::ZERO),
+            None => Duration::ZERO,
+        };
+        tokio::time::sleep(wait).await;
+
+        match create_backup(&db_path, &config).await {
+            Ok(path) => println!("Database backed up to {}", path.display()),
+            Err(e) => {
+                println!("Scheduled backup failed: {}", e); // error level
+                // Do not retry immediately if the backup directory is not writable
+                tokio::time::sleep(Duration::from_secs(3600)).await;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    /// Backups sorted newest first like list_backups returns them
+    fn backups(times: &[(i32, u32, u32, u32)]) -> Vec<(DateTime<Utc>, PathBuf)> {
+        let mut backups: Vec<(DateTime<Utc>, PathBuf)> = times.iter()
+            .map(|(year, month, day, hour)| {
+                let time = Utc.with_ymd_and_hms(*year, *month, *day, *hour, 0, 0).unwrap();
+                (time, PathBuf::from(format!("{}{}{}", BACKUP_FILE_PREFIX, time.format(BACKUP_TIMESTAMP_FORMAT), BACKUP_FILE_SUFFIX)))
+            })
+            .collect();
+        backups.sort_by_key(|backup| Reverse(backup.0));
+        backups
+    }
+
+    fn outdated(backups: &[(DateTime<Utc>, PathBuf)], keep_daily: usize, keep_weekly: usize) -> Vec<String> {
+        find_outdated_backups(backups, keep_daily, keep_weekly).iter().map(|path| path.display().to_string()).collect()
+    }
+
+    #[test]
+    fn keeps_the_newest_backup_of_each_day() {
+        // Wednesday 2023-11-15 and Tuesday 2023-11-14
+        let backups = backups(&[(2023, 11, 15, 12), (2023, 11, 15, 6), (2023, 11, 14, 12), (2023, 11, 13, 12)]);
+        assert_eq!(outdated(&backups, 2, 0), vec![
+            "social_credit-20231115-060000.db",
+            "social_credit-20231113-120000.db",
+        ]);
+    }
+
+    #[test]
+    fn keeps_the_newest_backup_of_each_week() {
+        // 2023-11-13 is a Monday, 2023-11-12 the Sunday of the week before
+        let backups = backups(&[(2023, 11, 14, 12), (2023, 11, 13, 12), (2023, 11, 12, 12), (2023, 11, 6, 12), (2023, 10, 30, 12)]);
+        assert_eq!(outdated(&backups, 1, 2), vec![
+            "social_credit-20231113-120000.db",
+            "social_credit-20231106-120000.db",
+            "social_credit-20231030-120000.db",
+        ]);
+        assert_eq!(outdated(&backups, 0, 5), vec![
+            "social_credit-20231113-120000.db",
+            "social_credit-20231106-120000.db",
+        ]);
+    }
+
+    #[test]
+    fn keeps_the_newest_backup_without_retention() {
+        let backups = backups(&[(2023, 11, 15, 12), (2023, 11, 14, 12)]);
+        assert_eq!(outdated(&backups, 0, 0), vec!["social_credit-20231114-120000.db"]);
+        assert!(outdated(&backups[..1], 0, 0).is_empty());
+        assert!(find_outdated_backups(&[], 0, 0).is_empty());
+    }
+}
@@ -0,0 +1,99 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+use rusqlite::Connection;
+use crate::data::budget::find_budget_spent_in_db;
+use crate::utils::cooldown_util::CooldownStrategy;
+
+const DEFAULT_DAILY_BUDGET: i64 = 100;
+const DAY_SECS: u64 = 24 * 60 * 60;
+
+/// The budget of absolute social credit every user can hand out per day, used with the budget cooldown strategy
+pub struct BudgetConfig {
+    pub daily_budget: i64,
+    /// The hour of the day in UTC when the budgets are reset
+    pub reset_hour: u64,
+}
+
+impl BudgetConfig {
+    /// Returns None if the room does not use the budget strategy
+    pub fn from_settings(settings: &HashMap<String, String>) -> Option<Self> {
+        if settings.get("cooldown_strategy").and_then(|value| CooldownStrategy::parse(value)) != Some(CooldownStrategy::Budget) {
+            return None;
+        }
+
+        Some(BudgetConfig {
+            daily_budget: settings.get("daily_budget").and_then(|value| value.parse::<i64>().ok()).filter(|value| *value > 0).unwrap_or(DEFAULT_DAILY_BUDGET),
+            reset_hour: settings.get("budget_reset_hour").and_then(|value| value.parse::<u64>().ok()).filter(|value| *value < 24).unwrap_or(0),
+        })
+    }
+
+    /// The start of the budget period the time is in
+    pub fn get_period_start(&self, time: SystemTime) -> SystemTime {
+        let secs = time.duration_since(SystemTime::UNIX_EPOCH).unwrap_or(Duration::ZERO).as_secs();
+        let offset = self.reset_hour * 60 * 60;
+        let start = ((secs + DAY_SECS - offset) / DAY_SECS * DAY_SECS + offset).saturating_sub(DAY_SECS);
+        SystemTime::UNIX_EPOCH + Duration::from_secs(start)
+    }
+
+    /// Seconds until the budgets are reset
+    pub fn get_time_till_reset(&self, time: SystemTime) -> i64 {
+        let next_reset = self.get_period_start(time) + Duration::from_secs(DAY_SECS);
+        next_reset.duration_since(time).unwrap_or(Duration::ZERO).as_secs() as i64
+    }
+
+    pub fn get_remaining_budget(&self, conn: &Arc<Mutex<Connection>>, user_room_data_id: i32, time: SystemTime) -> i64 {
+        let spent = find_budget_spent_in_db(conn, user_room_data_id, self.get_period_start(time));
+        (self.daily_budget - spent).max(0)
+    }
+}
+
+pub fn format_duration(secs: i64) -> String {
+    format!("{}h {}m", secs / 3600, secs % 3600 / 60)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOUR_SECS: u64 = 60 * 60;
+    // 2023-11-14 00:00:00 UTC
+    const MIDNIGHT: u64 = 1_699_920_000;
+
+    fn at(secs: u64) -> SystemTime {
+        SystemTime::UNIX_EPOCH + Duration::from_secs(secs)
+    }
+
+    fn config(reset_hour: u64) -> BudgetConfig {
+        BudgetConfig { daily_budget: 100, reset_hour }
+    }
+
+    #[test]
+    fn period_starts_at_midnight() {
+        assert_eq!(config(0).get_period_start(at(MIDNIGHT)), at(MIDNIGHT));
+        assert_eq!(config(0).get_period_start(at(MIDNIGHT + 1)), at(MIDNIGHT));
+        assert_eq!(config(0).get_period_start(at(MIDNIGHT - 1)), at(MIDNIGHT - DAY_SECS));
+    }
+
+    #[test]
+    fn period_starts_at_the_reset_hour() {
+        let reset = MIDNIGHT + 6 * HOUR_SECS;
+        assert_eq!(config(6).get_period_start(at(reset)), at(reset));
+        assert_eq!(config(6).get_period_start(at(reset + 20 * HOUR_SECS)), at(reset));
+        // Before the reset hour the period of the previous day is still running
+        assert_eq!(config(6).get_period_start(at(reset - 1)), at(reset - DAY_SECS));
+        assert_eq!(config(23).get_period_start(at(MIDNIGHT)), at(MIDNIGHT - HOUR_SECS));
+    }
+
+    #[test]
+    fn period_start_before_the_epoch() {
+        assert_eq!(config(0).get_period_start(SystemTime::UNIX_EPOCH), SystemTime::UNIX_EPOCH);
+        assert_eq!(config(6).get_period_start(at(HOUR_SECS)), SystemTime::UNIX_EPOCH);
+    }
+
+    #[test]
+    fn time_till_reset() {
+        assert_eq!(config(0).get_time_till_reset(at(MIDNIGHT)), DAY_SECS as i64);
+        assert_eq!(config(6).get_time_till_reset(at(MIDNIGHT + 5 * HOUR_SECS)), HOUR_SECS as i64);
+    }
+}
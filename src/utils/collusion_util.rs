@@ -0,0 +1,343 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+use matrix_sdk::room::Joined;
+use matrix_sdk::ruma::{OwnedUserId, UserId};
+use rusqlite::Connection;
+use crate::data::transaction::{find_reaction_edges_in_db, ReactionEdge};
+use crate::data::user::find_user_by_id_in_db;
+use crate::utils::mention_util::{get_display_name, MentionMessage};
+use crate::utils::user_util::disambiguate_names;
+
+const DEFAULT_WINDOW_DAYS: u64 = 7;
+const DEFAULT_MIN_REACTIONS: usize = 5;
+const DEFAULT_MUTUAL_PERCENT: usize = 50;
+const DEFAULT_BURST_REACTIONS: usize = 10;
+const DEFAULT_BURST_MINUTES: u64 = 60;
+const DEFAULT_CLIQUE_SIZE: usize = 3;
+const DEFAULT_DAMPEN_PERCENT: i32 = 50;
+const SUSPICION_CACHE_DURATION: Duration = Duration::from_secs(5 * 60);
+
+/// What happens to reactions between users that look like they farm each other's score
+#[derive(Clone, PartialEq)]
+pub enum CollusionAction {
+    /// Only listed with !suspicious
+    Flag,
+    /// Listed with !suspicious and the changes of further reactions between them are reduced
+    Dampen,
+}
+
+impl CollusionAction {
+    pub fn parse(text: &str) -> Option<Option<Self>> {
+        match text.to_lowercase().as_str() {
+            "off" => Some(None),
+            "flag" => Some(Some(CollusionAction::Flag)),
+            "dampen" => Some(Some(CollusionAction::Dampen)),
+            _ => None,
+        }
+    }
+}
+
+pub struct CollusionConfig {
+    pub action: CollusionAction,
+    /// How far back the reaction history is analysed
+    pub window: Duration,
+    /// Both users of a pair need to have reacted to each other at least this often
+    pub min_reactions: usize,
+    /// The share of the reactions both users gave that went to each other
+    pub mutual_percent: usize,
+    pub burst_reactions: usize,
+    pub burst_timespan: Duration,
+    /// A burst is only suspicious if it came from at most this many users
+    pub clique_size: usize,
+    /// The percent of the normal change that suspicious reactions still apply with the dampen action
+    pub dampen_percent: i32,
+}
+
+impl CollusionConfig {
+    /// Returns None if the room turned the detection off
+    pub fn from_settings(settings: &HashMap<String, String>) -> Option<Self> {
+        let action = match settings.get("collusion_action").and_then(|value| CollusionAction::parse(value)) {
+            Some(action) => action?,
+            None => CollusionAction::Flag,
+        };
+        let get_number = |key: &str, default: u64| settings.get(key).and_then(|value| value.parse::<u64>().ok()).filter(|value| *value > 0).unwrap_or(default);
+
+        Some(CollusionConfig {
+            action,
+            window: Duration::from_secs(get_number("collusion_window_days", DEFAULT_WINDOW_DAYS) * 24 * 60 * 60),
+            min_reactions: get_number("collusion_min_reactions", DEFAULT_MIN_REACTIONS as u64) as usize,
+            mutual_percent: get_number("collusion_mutual_percent", DEFAULT_MUTUAL_PERCENT as u64).min(100) as usize,
+            burst_reactions: get_number("collusion_burst_reactions", DEFAULT_BURST_REACTIONS as u64) as usize,
+            burst_timespan: Duration::from_secs(get_number("collusion_burst_minutes", DEFAULT_BURST_MINUTES) * 60),
+            clique_size: get_number("collusion_clique_size", DEFAULT_CLIQUE_SIZE as u64) as usize,
+            dampen_percent: settings.get("collusion_dampen_percent").and_then(|value| value.parse::<i32>().ok()).unwrap_or(DEFAULT_DAMPEN_PERCENT).clamp(0, 100),
+        })
+    }
+}
+
+pub enum Suspicion {
+    /// Two users that mostly react to each other
+    MutualPair { user_a: i32, user_b: i32, a_to_b: usize, b_to_a: usize, percent: usize },
+    /// A lot of reactions to one user in a short time from only a few users
+    Burst { recipient: i32, senders: Vec<i32>, reactions: usize },
+}
+
+impl Suspicion {
+    /// Whether a reaction from sender to recipient is part of this suspicion
+    pub fn involves(&self, sender: i32, recipient: i32) -> bool {
+        match self {
+            Suspicion::MutualPair { user_a, user_b, .. } => (sender == *user_a && recipient == *user_b) || (sender == *user_b && recipient == *user_a),
+            Suspicion::Burst { recipient: burst_recipient, senders, .. } => recipient == *burst_recipient && senders.contains(&sender),
+        }
+    }
+}
+
+pub fn find_suspicions(edges: &[ReactionEdge], config: &CollusionConfig) -> Vec<Suspicion> {
+    let mut suspicions = find_mutual_pairs(edges, config);
+    suspicions.extend(find_bursts(edges, config));
+    suspicions
+}
+
+fn find_mutual_pairs(edges: &[ReactionEdge], config: &CollusionConfig) -> Vec<Suspicion> {
+    let mut given: HashMap<i32, usize> = HashMap::new();
+    let mut pairs: HashMap<(i32, i32), usize> = HashMap::new();
+    for edge in edges {
+        *given.entry(edge.sender_user_id).or_default() += 1;
+        *pairs.entry((edge.sender_user_id, edge.recipient_user_id)).or_default() += 1;
+    }
+
+    let mut mutual_pairs = Vec::new();
+    for (&(user_a, user_b), &a_to_b) in &pairs {
+        // Every pair is seen from both sides, only the one with the lower id is reported
+        if user_a >= user_b {
+            continue;
+        }
+        let b_to_a = pairs.get(&(user_b, user_a)).copied().unwrap_or(0);
+        if a_to_b.min(b_to_a) < config.min_reactions {
+            continue;
+        }
+        let total = given.get(&user_a).copied().unwrap_or(0) + given.get(&user_b).copied().unwrap_or(0);
+        let percent = (a_to_b + b_to_a) * 100 / total.max(1);
+        if percent >= config.mutual_percent {
+            mutual_pairs.push((user_a, user_b, a_to_b, b_to_a, percent));
+        }
+    }
+    mutual_pairs.sort_by_key(|(.., percent)| std::cmp::Reverse(*percent));
+    mutual_pairs.into_iter()
+        .map(|(user_a, user_b, a_to_b, b_to_a, percent)| Suspicion::MutualPair { user_a, user_b, a_to_b, b_to_a, percent })
+        .collect()
+}
+
+/// Finds the largest burst of every recipient within burst_timespan that came from at most clique_size users
+fn find_bursts(edges: &[ReactionEdge], config: &CollusionConfig) -> Vec<Suspicion> {
+    let mut by_recipient: HashMap<i32, Vec<&ReactionEdge>> = HashMap::new();
+    for edge in edges {
+        by_recipient.entry(edge.recipient_user_id).or_default().push(edge);
+    }
+
+    let mut suspicions = Vec::new();
+    for (recipient, received) in by_recipient {
+        let mut largest: Option<(usize, Vec<i32>)> = None;
+        let mut start = 0;
+        for end in 0..received.len() {
+            while received[end].time.duration_since(received[start].time).unwrap_or(Duration::ZERO) > config.burst_timespan {
+                start += 1;
+            }
+            let reactions = end - start + 1;
+            if reactions < config.burst_reactions || largest.as_ref().is_some_and(|(largest, _)| *largest >= reactions) {
+                continue;
+            }
+            let mut senders: Vec<i32> = received[start..=end].iter().map(|edge| edge.sender_user_id).collect();
+            senders.sort_unstable();
+            senders.dedup();
+            if senders.len() <= config.clique_size {
+                largest = Some((reactions, senders));
+            }
+        }
+        if let Some((reactions, senders)) = largest {
+            suspicions.push(Suspicion::Burst { recipient, senders, reactions });
+        }
+    }
+    suspicions
+}
+
+/// Analyses the reactions of the room within the window of the config
+pub fn find_suspicions_in_room(conn: &Arc<Mutex<Connection>>, room_id: &str, config: &CollusionConfig, now: SystemTime) -> Vec<Suspicion> {
+    let since = now.checked_sub(config.window).unwrap_or(SystemTime::UNIX_EPOCH);
+    let edges = find_reaction_edges_in_db(conn, room_id, since).unwrap_or_default();
+    find_suspicions(&edges, config)
+}
+
+/// The suspicions of every room from the last analysis, so dampening does not analyse the whole window for every reaction.
+/// Reactions and changes of the settings are taken into account once the analysis is older than SUSPICION_CACHE_DURATION
+#[derive(Default)]
+pub struct SuspicionCache {
+    rooms: Mutex<HashMap<String, (SystemTime, Vec<Suspicion>)>>,
+}
+
+impl SuspicionCache {
+    /// Whether a reaction from sender to recipient at the time as_of is part of a suspicion in the room
+    pub fn is_suspicious(&self, conn: &Arc<Mutex<Connection>>, room_id: &str, config: &CollusionConfig, sender: i32, recipient: i32, as_of: SystemTime) -> bool {
+        let mut rooms = self.rooms.lock().unwrap();
+        // Catching up analyses reactions in the past, so the analysis can be newer than as_of
+        let is_fresh = |analysed_at: &SystemTime| as_of.duration_since(*analysed_at).unwrap_or_else(|e| e.duration()) < SUSPICION_CACHE_DURATION;
+        if !rooms.get(room_id).is_some_and(|(analysed_at, _)| is_fresh(analysed_at)) {
+            rooms.insert(room_id.to_string(), (as_of, find_suspicions_in_room(conn, room_id, config, as_of)));
+        }
+        rooms.get(room_id).is_some_and(|(_, suspicions)| suspicions.iter().any(|suspicion| suspicion.involves(sender, recipient)))
+    }
+}
+
+pub async fn get_suspicious_answer(conn: &Arc<Mutex<Connection>>, room: &Joined, config: Option<&CollusionConfig>) -> MentionMessage {
+    let mut message = MentionMessage::default();
+    let config = match config {
+        Some(config) => config,
+        None => {
+            message.push("Collusion detection is off in this room", "Collusion detection is off in this room");
+            return message;
+        }
+    };
+    let suspicions = find_suspicions_in_room(conn, room.room_id().as_str(), config, SystemTime::now());
+    if suspicions.is_empty() {
+        message.push("No suspicious reactions", "No suspicious reactions");
+        return message;
+    }
+
+    let mut user_ids: Vec<i32> = suspicions.iter()
+        .flat_map(|suspicion| match suspicion {
+            Suspicion::MutualPair { user_a, user_b, .. } => vec![*user_a, *user_b],
+            Suspicion::Burst { recipient, senders, .. } => std::iter::once(*recipient).chain(senders.iter().copied()).collect(),
+        })
+        .collect();
+    user_ids.sort_unstable();
+    user_ids.dedup();
+    let mut mxids = Vec::new();
+    let mut names = Vec::new();
+    for id in user_ids {
+        if let Some(user_id) = find_user_by_id_in_db(conn, id).and_then(|user| UserId::parse(user.mxid).ok()) {
+            names.push((user_id.to_string(), get_display_name(room, &user_id).await));
+            mxids.push((id, user_id));
+        }
+    }
+    disambiguate_names(&mut names);
+    let users: HashMap<i32, (OwnedUserId, String)> = mxids.into_iter().zip(names).map(|((id, user_id), (_, name))| (id, (user_id, name))).collect();
+    let push_user = |message: &mut MentionMessage, id: i32| match users.get(&id) {
+        Some((user_id, name)) => message.push_mention(user_id, name),
+        None => message.push(&format!("unknown user {}", id), &format!("unknown user {}", id)),
+    };
+
+    message.push("Suspicious reactions:", "<h3>Suspicious reactions:</h3>");
+    for suspicion in suspicions {
+        message.push("\n", "<br>");
+        match suspicion {
+            Suspicion::MutualPair { user_a, user_b, a_to_b, b_to_a, percent } => {
+                push_user(&mut message, user_a);
+                message.push(" and ", " and ");
+                push_user(&mut message, user_b);
+                let text = format!(" reacted to each other {} and {} times, {}% of the reactions they gave", a_to_b, b_to_a, percent);
+                message.push(&text, &text);
+            },
+            Suspicion::Burst { recipient, senders, reactions } => {
+                push_user(&mut message, recipient);
+                let text = format!(" got {} reactions within {} minutes from only ", reactions, config.burst_timespan.as_secs() / 60);
+                message.push(&text, &text);
+                for (index, sender) in senders.into_iter().enumerate() {
+                    if index > 0 {
+                        message.push(", ", ", ");
+                    }
+                    push_user(&mut message, sender);
+                }
+            },
+        }
+    }
+    message
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> CollusionConfig {
+        CollusionConfig {
+            action: CollusionAction::Flag,
+            window: Duration::from_secs(DEFAULT_WINDOW_DAYS * 24 * 60 * 60),
+            min_reactions: 3,
+            mutual_percent: 50,
+            burst_reactions: 4,
+            burst_timespan: Duration::from_secs(60 * 60),
+            clique_size: 2,
+            dampen_percent: DEFAULT_DAMPEN_PERCENT,
+        }
+    }
+
+    fn edge(sender_user_id: i32, recipient_user_id: i32, minutes: u64) -> ReactionEdge {
+        ReactionEdge { sender_user_id, recipient_user_id, time: SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000 + minutes * 60) }
+    }
+
+    fn edges(sender_user_id: i32, recipient_user_id: i32, count: u64, start_minutes: u64, step_minutes: u64) -> Vec<ReactionEdge> {
+        (0..count).map(|index| edge(sender_user_id, recipient_user_id, start_minutes + index * step_minutes)).collect()
+    }
+
+    #[test]
+    fn mutual_pair() {
+        let mut reactions = edges(1, 2, 3, 0, 600);
+        reactions.extend(edges(2, 1, 4, 0, 600));
+        reactions.extend(edges(1, 3, 1, 0, 600));
+        let suspicions = find_mutual_pairs(&reactions, &config());
+        assert_eq!(suspicions.len(), 1);
+        assert!(matches!(suspicions[0], Suspicion::MutualPair { user_a: 1, user_b: 2, a_to_b: 3, b_to_a: 4, percent: 87 }));
+        assert!(suspicions[0].involves(2, 1));
+        assert!(!suspicions[0].involves(1, 3));
+    }
+
+    #[test]
+    fn mutual_pair_below_min_reactions() {
+        let mut reactions = edges(1, 2, 2, 0, 600);
+        reactions.extend(edges(2, 1, 10, 0, 600));
+        assert!(find_mutual_pairs(&reactions, &config()).is_empty());
+    }
+
+    #[test]
+    fn mutual_pair_below_mutual_percent() {
+        let mut reactions = edges(1, 2, 3, 0, 600);
+        reactions.extend(edges(2, 1, 3, 0, 600));
+        reactions.extend(edges(1, 3, 4, 0, 600));
+        reactions.extend(edges(2, 4, 3, 0, 600));
+        // 6 of 13 reactions went to each other
+        assert!(find_mutual_pairs(&reactions, &config()).is_empty());
+        let mut config = config();
+        config.mutual_percent = 46;
+        assert_eq!(find_mutual_pairs(&reactions, &config).len(), 1);
+    }
+
+    #[test]
+    fn burst_from_a_clique() {
+        let mut reactions = edges(1, 3, 3, 0, 10);
+        reactions.extend(edges(2, 3, 2, 5, 10));
+        reactions.sort_by_key(|edge| edge.time);
+        let suspicions = find_bursts(&reactions, &config());
+        assert_eq!(suspicions.len(), 1);
+        assert!(matches!(&suspicions[0], Suspicion::Burst { recipient: 3, senders, reactions: 5 } if senders == &vec![1, 2]));
+        assert!(suspicions[0].involves(2, 3));
+        assert!(!suspicions[0].involves(3, 1));
+    }
+
+    #[test]
+    fn burst_at_the_edge_of_the_timespan() {
+        // 4 reactions within exactly 60 minutes are a burst, within 61 minutes not
+        assert_eq!(find_bursts(&edges(1, 3, 4, 0, 20), &config()).len(), 1);
+        let mut reactions = edges(1, 3, 3, 0, 20);
+        reactions.push(edge(1, 3, 61));
+        assert!(find_bursts(&reactions, &config()).is_empty());
+    }
+
+    #[test]
+    fn burst_from_too_many_users() {
+        let reactions: Vec<ReactionEdge> = (1..=4).map(|sender| edge(sender, 9, sender as u64)).collect();
+        assert!(find_bursts(&reactions, &config()).is_empty());
+        let mut config = config();
+        config.clique_size = 4;
+        assert_eq!(find_bursts(&reactions, &config).len(), 1);
+    }
+}
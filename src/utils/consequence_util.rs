@@ -0,0 +1,273 @@
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+use matrix_sdk::Client;
+use matrix_sdk::room::Joined;
+use matrix_sdk::ruma::{Int, RoomId, UserId};
+use matrix_sdk::ruma::api::client::state::get_state_events_for_key::v3::Request as GetStateRequest;
+use matrix_sdk::ruma::events::StateEventType;
+use matrix_sdk::ruma::events::room::power_levels::{PowerLevelAction, RoomPowerLevels, RoomPowerLevelsEventContent};
+use rusqlite::Connection;
+use crate::data::consequence::{AppliedConsequence, Consequence, deactivate_applied_consequence, delete_applied_consequence, find_all_consequences_for_room_in_db, find_all_room_ids_with_consequences_in_db, find_applied_consequence_in_db, find_consequence_in_db, find_applied_consequences_for_consequence_in_db, find_expired_applied_consequences_in_db, insert_applied_consequence};
+use crate::data::user::HtmlAndTextAnswer;
+use crate::data::user_room_data::find_all_scores_in_db;
+
+/// How often expired consequences are reverted and all scores are checked again,
+/// which also catches changes that were not made by a reaction like decay
+const CONSEQUENCE_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Parses the arguments of !consequences add: below|above <threshold> power_level <level> [minutes], mute [minutes] or kick
+pub fn parse_consequence(room_id: &str, args: &[&str]) -> Option<Consequence> {
+    let below = match *args.first()? {
+        "below" => true,
+        "above" => false,
+        _ => return None,
+    };
+    let threshold = args.get(1)?.parse::<i32>().ok()?;
+    let parse_minutes = |minutes: Option<&&str>| match minutes {
+        Some(minutes) => minutes.parse::<i64>().ok().filter(|minutes| *minutes > 0).map(Some),
+        None => Some(None),
+    };
+    let (action, power_level, duration_minutes) = match &args[2..] {
+        ["power_level", level, rest @ ..] if rest.len() <= 1 => ("power_level", Some(level.parse::<i64>().ok()?), parse_minutes(rest.first())?),
+        ["mute", rest @ ..] if rest.len() <= 1 => ("mute", None, parse_minutes(rest.first())?),
+        ["kick"] => ("kick", None, None),
+        _ => return None,
+    };
+
+    Some(Consequence {
+        id: 0,
+        room_id: room_id.to_string(),
+        below,
+        threshold,
+        action: action.to_string(),
+        power_level,
+        duration_minutes,
+    })
+}
+
+pub fn format_consequence(consequence: &Consequence) -> String {
+    let mut text = format!("{} {}: ", if consequence.below { "below" } else { "above" }, consequence.threshold);
+    match consequence.action.as_str() {
+        "power_level" => text.push_str(&format!("set power level to {}", consequence.power_level.unwrap_or_default())),
+        action => text.push_str(action),
+    }
+    match consequence.duration_minutes {
+        Some(minutes) => text.push_str(&format!(" for {} minutes", minutes)),
+        None if consequence.action != "kick" => text.push_str(" until the score recovers"),
+        None => {},
+    }
+    text
+}
+
+pub fn get_consequence_list_answer(conn: &Arc<Mutex<Connection>>, room_id: &str) -> HtmlAndTextAnswer {
+    let consequences = find_all_consequences_for_room_in_db(conn, room_id).unwrap_or_default();
+    if consequences.is_empty() {
+        return HtmlAndTextAnswer {
+            html: String::from("No consequences, use the !help command to see how to add consequences"),
+            text: String::from("No consequences, use the !help command to see how to add consequences"),
+        };
+    }
+
+    let mut text_body = String::from("Consequences:");
+    let mut html_body = String::from("<h3>Consequences:</h3>");
+    for consequence in consequences {
+        text_body.push_str(&format!("\n{}: {}", consequence.id, format_consequence(&consequence)));
+        html_body.push_str(&format!("<br><b>{}</b>: {}", consequence.id, format_consequence(&consequence)));
+    }
+
+    HtmlAndTextAnswer {
+        html: html_body,
+        text: text_body,
+    }
+}
+
+/// Loads the power levels from the homeserver, the state in the store can be outdated right after the bot changed them
+async fn get_power_levels(room: &Joined) -> anyhow::Result<RoomPowerLevels> {
+    let request = GetStateRequest::new(room.room_id(), StateEventType::RoomPowerLevels, "");
+    let response = room.client().send(request, None).await?;
+    let content: RoomPowerLevelsEventContent = response.content.deserialize_as()?;
+    Ok(content.into())
+}
+
+/// The bot can only change the power level of users below its own level and not set a level above its own
+fn can_set_power_level(power_levels: &RoomPowerLevels, bot_user_id: &UserId, user_id: &UserId, power_level: Int) -> bool {
+    let bot_power_level = power_levels.for_user(bot_user_id);
+    power_levels.user_can_do(bot_user_id, PowerLevelAction::SendState(StateEventType::RoomPowerLevels))
+        && power_levels.for_user(user_id) < bot_power_level
+        && power_level <= bot_power_level
+}
+
+/// The power level that is set by the consequence, mute sets a level just below the level that is needed to send messages
+fn get_target_power_level(consequence: &Consequence, power_levels: &RoomPowerLevels) -> Option<Int> {
+    match consequence.action.as_str() {
+        "power_level" => consequence.power_level.and_then(Int::new),
+        "mute" => Some(power_levels.events_default - Int::from(1)),
+        _ => None,
+    }
+}
+
+async fn set_power_level(room: &Joined, mut power_levels: RoomPowerLevels, user_id: &UserId, power_level: Int) -> anyhow::Result<()> {
+    if power_level == power_levels.users_default {
+        power_levels.users.remove(user_id);
+    }
+    else {
+        power_levels.users.insert(user_id.to_owned(), power_level);
+    }
+    room.send_state_event(RoomPowerLevelsEventContent::from(power_levels)).await?;
+    Ok(())
+}
+
+/// Executes the consequence if the bot has the power level for it.
+/// Returns the power level of the user before the change, which is restored when the consequence is reverted
+async fn execute_consequence(room: &Joined, bot_user_id: &UserId, consequence: &Consequence, user_id: &UserId) -> anyhow::Result<Option<i64>> {
+    let power_levels = get_power_levels(room).await?;
+    let previous_power_level = power_levels.for_user(user_id);
+
+    if consequence.action == "kick" {
+        if !power_levels.user_can_do(bot_user_id, PowerLevelAction::Kick) || previous_power_level >= power_levels.for_user(bot_user_id) {
+            anyhow::bail!("The bot is not allowed to kick {}", user_id);
+        }
+        let reason = format!("Social credit {}", format_consequence(consequence));
+        room.kick_user(user_id, Some(&reason)).await?;
+        return Ok(None);
+    }
+
+    let power_level = match get_target_power_level(consequence, &power_levels) {
+        Some(power_level) => power_level,
+        None => anyhow::bail!("Unknown consequence action {}", consequence.action),
+    };
+    if !can_set_power_level(&power_levels, bot_user_id, user_id, power_level) {
+        anyhow::bail!("The bot is not allowed to set the power level of {} to {}", user_id, power_level);
+    }
+    set_power_level(room, power_levels, user_id, power_level).await?;
+    Ok(Some(previous_power_level.into()))
+}
+
+/// Restores the power level the user had before the consequence, unless it was changed by someone else since
+async fn revert_consequence(room: &Joined, bot_user_id: &UserId, consequence: &Consequence, applied: &AppliedConsequence) -> anyhow::Result<()> {
+    let previous_power_level = match applied.previous_power_level.and_then(Int::new) {
+        Some(previous_power_level) => previous_power_level,
+        None => return Ok(()),
+    };
+    let user_id = <&UserId>::try_from(applied.mxid.as_str())?;
+    let power_levels = get_power_levels(room).await?;
+    if get_target_power_level(consequence, &power_levels) != Some(power_levels.for_user(user_id)) {
+        println!("Power level of {} in room {} was changed by someone else, not reverting consequence {}", user_id, applied.room_id, consequence.id); // debug level
+        return Ok(());
+    }
+    if !can_set_power_level(&power_levels, bot_user_id, user_id, previous_power_level) {
+        anyhow::bail!("The bot is not allowed to restore the power level of {}", user_id);
+    }
+    set_power_level(room, power_levels, user_id, previous_power_level).await
+}
+
+/// Applies the consequences of the room the user has reached with the social credit and reverts the ones the user recovered from.
+/// Reverts happen first and newest first, so a power level that overlapping consequences changed one after another
+/// is restored step by step to the level from before the first of them
+pub async fn evaluate_consequences(conn: &Arc<Mutex<Connection>>, room: &Joined, bot_user_id: &UserId, user_id: &UserId, social_credit: i32) {
+    let consequences = find_all_consequences_for_room_in_db(conn, room.room_id().as_str()).unwrap_or_default();
+    let mut recovered = Vec::new();
+    let mut reached = Vec::new();
+    for consequence in consequences {
+        let applied = find_applied_consequence_in_db(conn, consequence.id, user_id.as_str());
+        match (consequence.applies_to(social_credit), applied) {
+            (true, None) => reached.push(consequence),
+            (false, Some(applied)) => recovered.push((consequence, applied)),
+            _ => {},
+        }
+    }
+
+    recovered.sort_by_key(|(_, applied)| std::cmp::Reverse(applied.id));
+    for (consequence, applied) in recovered {
+        if applied.active {
+            match revert_consequence(room, bot_user_id, &consequence, &applied).await {
+                Ok(_) => println!("Reverted consequence {} of {} in room {}, the score recovered", consequence.id, user_id, room.room_id()), // debug level
+                Err(e) => println!("Unable to revert consequence {} of {} in room {}: {}", consequence.id, user_id, room.room_id(), e), // error level
+            }
+        }
+        if let Err(e) = delete_applied_consequence(conn, applied.id) {
+            println!("Unable to delete applied consequence from db: {}", e); // error level
+        }
+    }
+
+    for consequence in reached {
+        let result = execute_consequence(room, bot_user_id, &consequence, user_id).await;
+        let previous_power_level = match &result {
+            Ok(previous_power_level) => {
+                println!("Applied consequence {} ({}) to {} in room {}", consequence.id, format_consequence(&consequence), user_id, room.room_id()); // debug level
+                *previous_power_level
+            },
+            Err(e) => {
+                println!("Unable to apply consequence {} to {} in room {}: {}", consequence.id, user_id, room.room_id(), e); // error level
+                None
+            }
+        };
+        // Also stored if it failed, so it is not tried again on every change until the score recovers
+        let applied = AppliedConsequence {
+            id: 0,
+            consequence_id: consequence.id,
+            room_id: room.room_id().to_string(),
+            mxid: user_id.to_string(),
+            previous_power_level,
+            expires_at: consequence.duration_minutes.map(|minutes| SystemTime::now() + Duration::from_secs(minutes as u64 * 60)),
+            active: previous_power_level.is_some(),
+        };
+        if let Err(e) = insert_applied_consequence(conn, &applied) {
+            println!("Unable to insert applied consequence into db: {}", e); // error level
+        }
+    }
+}
+
+/// Reverts all active applications of the consequence, used before the consequence is removed
+pub async fn revert_all_applied_consequences(conn: &Arc<Mutex<Connection>>, room: &Joined, bot_user_id: &UserId, consequence: &Consequence) {
+    let applied_consequences = find_applied_consequences_for_consequence_in_db(conn, consequence.id).unwrap_or_default();
+    for applied in applied_consequences.iter().filter(|applied| applied.active) {
+        if let Err(e) = revert_consequence(room, bot_user_id, consequence, applied).await {
+            println!("Unable to revert consequence {} of {} in room {}: {}", consequence.id, applied.mxid, room.room_id(), e); // error level
+        }
+    }
+}
+
+async fn revert_expired_consequences(client: &Client, conn: &Arc<Mutex<Connection>>, bot_user_id: &UserId) {
+    let expired = find_expired_applied_consequences_in_db(conn, SystemTime::now()).unwrap_or_default();
+    for applied in expired {
+        let room = RoomId::parse(&applied.room_id).ok().and_then(|room_id| client.get_joined_room(&room_id));
+        let consequence = find_consequence_in_db(conn, &applied.room_id, applied.consequence_id);
+        if let (Some(room), Some(consequence)) = (room, consequence) {
+            match revert_consequence(&room, bot_user_id, &consequence, &applied).await {
+                Ok(_) => println!("Reverted consequence {} of {} in room {}, the duration expired", consequence.id, applied.mxid, applied.room_id), // debug level
+                Err(e) => println!("Unable to revert consequence {} of {} in room {}: {}", consequence.id, applied.mxid, applied.room_id, e), // error level
+            }
+        }
+        // The entry is kept so the consequence is not applied again before the score recovered
+        if let Err(e) = deactivate_applied_consequence(conn, applied.id) {
+            println!("Unable to update applied consequence in db: {}", e); // error level
+        }
+    }
+}
+
+/// Reverts consequences whose duration expired and checks the scores of all users in rooms with consequences
+pub async fn run_consequence_schedule(client: Client, conn: Arc<Mutex<Connection>>) {
+    let bot_user_id = match client.user_id() {
+        Some(user_id) => user_id.to_owned(),
+        None => return,
+    };
+    loop {
+        revert_expired_consequences(&client, &conn, &bot_user_id).await;
+
+        let room_ids = find_all_room_ids_with_consequences_in_db(&conn).unwrap_or_else(|e| {
+            println!("Unable to load rooms with consequences: {}", e); // error level
+            Vec::new()
+        });
+        for room in client.joined_rooms().into_iter().filter(|room| room_ids.contains(&room.room_id().to_string())) {
+            let scores = find_all_scores_in_db(&conn, room.room_id().as_str(), bot_user_id.as_str()).unwrap_or_default();
+            for (mxid, social_credit) in scores {
+                if let Ok(user_id) = UserId::parse(&mxid) {
+                    evaluate_consequences(&conn, &room, &bot_user_id, &user_id, social_credit).await;
+                }
+            }
+        }
+
+        tokio::time::sleep(CONSEQUENCE_CHECK_INTERVAL).await;
+    }
+}
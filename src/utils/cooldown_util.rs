@@ -0,0 +1,316 @@
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime};
+use crate::data::transaction::Transaction;
+
+const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+#[derive(Clone, PartialEq)]
+pub enum CooldownStrategy {
+    /// At most reaction_limit reactions within any reaction_timespan minutes
+    Window,
+    /// Up to bucket_size reactions at once, one more reaction becomes available every bucket_refill_minutes
+    Bucket,
+    /// No limit on the number of reactions, instead every user can hand out daily_budget absolute social credit per day
+    Budget,
+}
+
+impl CooldownStrategy {
+    pub fn parse(text: &str) -> Option<Self> {
+        match text.to_lowercase().as_str() {
+            "window" => Some(CooldownStrategy::Window),
+            "bucket" => Some(CooldownStrategy::Bucket),
+            "budget" => Some(CooldownStrategy::Budget),
+            _ => None,
+        }
+    }
+}
+
+/// The rate limits for reactions in a room, everything that was not changed with !config
+/// uses REACTION_LIMIT and REACTION_TIMESPAN
+#[derive(Clone)]
+pub struct CooldownConfig {
+    pub strategy: CooldownStrategy,
+    pub reaction_limit: i32,
+    pub reaction_timespan: i32,
+    pub bucket_size: i32,
+    pub bucket_refill_minutes: i32,
+    /// If one of these is set, positive and negative reactions are limited separately,
+    /// a sign without its own limit uses reaction_limit or bucket_size
+    pub positive_limit: Option<i32>,
+    pub negative_limit: Option<i32>,
+    /// How often a user can change the score of the same user within a day
+    pub recipient_daily_limit: Option<i32>,
+}
+
+impl CooldownConfig {
+    pub fn new(reaction_limit: i32, reaction_timespan: i32) -> Self {
+        CooldownConfig {
+            strategy: CooldownStrategy::Window,
+            reaction_limit,
+            reaction_timespan,
+            bucket_size: reaction_limit,
+            bucket_refill_minutes: (reaction_timespan / reaction_limit.max(1)).max(1),
+            positive_limit: None,
+            negative_limit: None,
+            recipient_daily_limit: None,
+        }
+    }
+
+    /// Applies the settings of a room on top of the defaults, invalid values are ignored
+    pub fn from_settings(settings: &HashMap<String, String>, reaction_limit: i32, reaction_timespan: i32) -> Self {
+        let mut config = CooldownConfig::new(reaction_limit, reaction_timespan);
+        let get_number = |key: &str| settings.get(key).and_then(|value| value.parse::<i32>().ok()).filter(|value| *value > 0);
+        // A limit of 0 turns the limit off
+        let get_limit = |key: &str| settings.get(key).and_then(|value| value.parse::<i32>().ok()).filter(|value| *value >= 0);
+
+        if let Some(strategy) = settings.get("cooldown_strategy").and_then(|value| CooldownStrategy::parse(value)) {
+            config.strategy = strategy;
+        }
+        config.reaction_limit = get_limit("reaction_limit").unwrap_or(config.reaction_limit);
+        config.reaction_timespan = get_number("reaction_timespan").unwrap_or(config.reaction_timespan);
+        config.bucket_size = get_limit("bucket_size").unwrap_or(config.bucket_size);
+        config.bucket_refill_minutes = get_number("bucket_refill_minutes").unwrap_or(config.bucket_refill_minutes);
+        config.positive_limit = get_limit("positive_limit");
+        config.negative_limit = get_limit("negative_limit");
+        config.recipient_daily_limit = get_number("recipient_daily_limit");
+        config
+    }
+
+    /// How far back the reactions of a user are needed to evaluate all limits
+    pub fn history_span(&self) -> Duration {
+        let window = Duration::from_secs(self.reaction_timespan.max(0) as u64 * 60);
+        // The limits per sign replace bucket_size, the largest bucket needs the longest history
+        let size = self.bucket_size.max(self.positive_limit.unwrap_or(0)).max(self.negative_limit.unwrap_or(0));
+        let bucket = Duration::from_secs(size.max(0) as u64 * self.bucket_refill_minutes.max(1) as u64 * 60);
+        let span = match self.strategy {
+            CooldownStrategy::Window | CooldownStrategy::Budget => window,
+            CooldownStrategy::Bucket => bucket,
+        };
+        if self.recipient_daily_limit.is_some() { span.max(DAY) } else { span }
+    }
+
+    /// Checks if a user with the given recent reactions is able to react with an emoji worth social_credit at the time as_of,
+    /// returns 0 if the user can react,
+    /// returns the time in seconds until the user can react again otherwise
+    pub fn get_time_till_user_can_react(&self, history: &[Transaction], as_of: SystemTime, social_credit: i32) -> i64 {
+        let split_by_sign = self.positive_limit.is_some() || self.negative_limit.is_some();
+        let (times, sign_limit): (Vec<SystemTime>, Option<i32>) = if split_by_sign {
+            let is_positive = social_credit >= 0;
+            let times = history.iter().filter(|transaction| (transaction.emoji_social_credit.unwrap_or(transaction.delta) >= 0) == is_positive).map(|transaction| transaction.time).collect();
+            (times, if is_positive { self.positive_limit } else { self.negative_limit })
+        }
+        else {
+            (history.iter().map(|transaction| transaction.time).collect(), None)
+        };
+
+        match self.strategy {
+            CooldownStrategy::Window => {
+                let period = Duration::from_secs(self.reaction_timespan.max(0) as u64 * 60);
+                get_time_till_window_has_room(&times, as_of, period, sign_limit.unwrap_or(self.reaction_limit))
+            },
+            CooldownStrategy::Bucket => {
+                let refill = Duration::from_secs(self.bucket_refill_minutes.max(1) as u64 * 60);
+                get_time_till_bucket_has_token(&times, as_of, refill, sign_limit.unwrap_or(self.bucket_size))
+            },
+            // The budget is checked once the change is known, only the limits per sign still count reactions
+            CooldownStrategy::Budget => match sign_limit {
+                Some(limit) => get_time_till_window_has_room(&times, as_of, Duration::from_secs(self.reaction_timespan.max(0) as u64 * 60), limit),
+                None => 0,
+            },
+        }
+    }
+
+    /// Checks if the user already changed the score of the recipient too often within the last day,
+    /// returns the time in seconds until the user can change it again or 0
+    pub fn get_time_till_user_can_change_recipient(&self, history: &[Transaction], recipient_user_room_data_id: i32, as_of: SystemTime) -> i64 {
+        let limit = match self.recipient_daily_limit {
+            Some(limit) => limit,
+            None => return 0,
+        };
+        let times: Vec<SystemTime> = history.iter()
+            .filter(|transaction| transaction.user_room_data_id == recipient_user_room_data_id)
+            .map(|transaction| transaction.time)
+            .collect();
+        get_time_till_window_has_room(&times, as_of, DAY, limit)
+    }
+}
+
+/// Checks if the user already made limit transfers within the last day, history are the transfer transactions the user caused.
+/// Returns the time in seconds until the user can transfer again or 0
+pub fn get_time_till_user_can_transfer(history: &[Transaction], sender_user_room_data_id: i32, as_of: SystemTime, limit: i32) -> i64 {
+    // Every transfer is recorded twice, only the credit of the recipient is counted
+    let times: Vec<SystemTime> = history.iter()
+        .filter(|transaction| transaction.user_room_data_id != sender_user_room_data_id)
+        .map(|transaction| transaction.time)
+        .collect();
+    get_time_till_window_has_room(&times, as_of, DAY, limit)
+}
+
+/// Sliding window, times have to be sorted oldest first. Times after as_of due to clock skew between servers count as recent.
+/// A limit of 0 or less disables the window
+fn get_time_till_window_has_room(times: &[SystemTime], as_of: SystemTime, period: Duration, limit: i32) -> i64 {
+    if limit <= 0 {
+        return 0;
+    }
+    let recent: Vec<&SystemTime> = times.iter()
+        .filter(|time| as_of.duration_since(**time).unwrap_or(Duration::ZERO) <= period)
+        .collect();
+    let limit = limit as usize;
+    if recent.len() < limit {
+        return 0;
+    }
+
+    // The window has room again as soon as enough reactions left it to get below the limit
+    let expires_at = *recent[recent.len() - limit] + period;
+    expires_at.duration_since(as_of).unwrap_or(Duration::ZERO).as_secs() as i64
+}
+
+/// Token bucket that starts full before the first reaction, times have to be sorted oldest first.
+/// A size of 0 or less disables the bucket
+fn get_time_till_bucket_has_token(times: &[SystemTime], as_of: SystemTime, refill: Duration, size: i32) -> i64 {
+    if size <= 0 {
+        return 0;
+    }
+    let size = size as f64;
+    let refill_secs = refill.as_secs_f64();
+    let mut tokens = size;
+    let mut last_time: Option<SystemTime> = None;
+    let refill_until = |tokens: f64, last_time: Option<SystemTime>, time: SystemTime| match last_time {
+        Some(last_time) => (tokens + time.duration_since(last_time).unwrap_or(Duration::ZERO).as_secs_f64() / refill_secs).min(size),
+        None => tokens,
+    };
+
+    for time in times {
+        tokens = refill_until(tokens, last_time, *time) - 1.0;
+        last_time = Some(*time);
+    }
+    tokens = refill_until(tokens, last_time, as_of);
+
+    if tokens >= 1.0 {
+        return 0;
+    }
+    ((1.0 - tokens) * refill_secs).ceil() as i64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MINUTE: Duration = Duration::from_secs(60);
+
+    fn at(minutes: u64) -> SystemTime {
+        SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000) + MINUTE * minutes as u32
+    }
+
+    fn reaction(minutes: u64, delta: i32, emoji_social_credit: i32) -> Transaction {
+        let mut transaction = Transaction::new(1, Some(2), "reaction", delta, 0, None);
+        transaction.time = at(minutes);
+        transaction.emoji_social_credit = Some(emoji_social_credit);
+        transaction
+    }
+
+    #[test]
+    fn window_with_room() {
+        assert_eq!(get_time_till_window_has_room(&[], at(0), MINUTE * 10, 2), 0);
+        assert_eq!(get_time_till_window_has_room(&[at(0)], at(1), MINUTE * 10, 2), 0);
+    }
+
+    #[test]
+    fn window_is_full() {
+        // The oldest reaction leaves the window at minute 10
+        assert_eq!(get_time_till_window_has_room(&[at(0), at(5)], at(6), MINUTE * 10, 2), 4 * 60);
+        assert_eq!(get_time_till_window_has_room(&[at(0), at(5)], at(10), MINUTE * 10, 2), 0);
+        // Reactions before the window do not count
+        assert_eq!(get_time_till_window_has_room(&[at(0), at(20), at(25)], at(26), MINUTE * 10, 3), 0);
+    }
+
+    #[test]
+    fn window_with_limit_0_is_disabled() {
+        assert_eq!(get_time_till_window_has_room(&[], at(0), MINUTE * 10, 0), 0);
+        assert_eq!(get_time_till_window_has_room(&[at(0), at(1)], at(2), MINUTE * 10, 0), 0);
+        assert_eq!(get_time_till_window_has_room(&[at(0)], at(1), MINUTE * 10, -1), 0);
+    }
+
+    #[test]
+    fn window_counts_reactions_from_the_future() {
+        assert_eq!(get_time_till_window_has_room(&[at(5)], at(0), MINUTE * 10, 1), 15 * 60);
+    }
+
+    #[test]
+    fn bucket_starts_full() {
+        assert_eq!(get_time_till_bucket_has_token(&[], at(0), MINUTE, 3), 0);
+        assert_eq!(get_time_till_bucket_has_token(&[at(0), at(0)], at(0), MINUTE, 3), 0);
+    }
+
+    #[test]
+    fn bucket_is_empty() {
+        assert_eq!(get_time_till_bucket_has_token(&[at(0), at(0), at(0)], at(0), MINUTE * 2, 3), 2 * 60);
+        assert_eq!(get_time_till_bucket_has_token(&[at(0), at(0), at(0)], at(1), MINUTE * 2, 3), 60);
+        assert_eq!(get_time_till_bucket_has_token(&[at(0), at(0), at(0)], at(2), MINUTE * 2, 3), 0);
+    }
+
+    #[test]
+    fn bucket_does_not_refill_beyond_its_size() {
+        // After a long pause the bucket holds 2 tokens, not more
+        assert_eq!(get_time_till_bucket_has_token(&[at(0), at(0), at(100), at(100)], at(100), MINUTE, 2), 60);
+    }
+
+    #[test]
+    fn bucket_with_size_0_is_disabled() {
+        assert_eq!(get_time_till_bucket_has_token(&[], at(0), MINUTE, 0), 0);
+        assert_eq!(get_time_till_bucket_has_token(&[at(0), at(0)], at(0), MINUTE, 0), 0);
+    }
+
+    #[test]
+    fn bucket_with_reactions_from_the_future() {
+        assert_eq!(get_time_till_bucket_has_token(&[at(5)], at(0), MINUTE, 1), 60);
+    }
+
+    #[test]
+    fn limits_per_sign_use_the_emoji() {
+        let mut config = CooldownConfig::new(1, 10);
+        config.positive_limit = Some(1);
+        // The change of the negative emoji was limited to 0 by the bounds, it still counts as a negative reaction
+        let history = [reaction(0, 0, -5)];
+        assert_eq!(config.get_time_till_user_can_react(&history, at(1), 5), 0);
+        assert_eq!(config.get_time_till_user_can_react(&history, at(1), -5), 9 * 60);
+    }
+
+    #[test]
+    fn limits_per_sign_without_emoji_use_the_delta() {
+        let mut config = CooldownConfig::new(1, 10);
+        config.negative_limit = Some(1);
+        let mut transaction = reaction(0, -3, 0);
+        transaction.emoji_social_credit = None;
+        assert_eq!(config.get_time_till_user_can_react(&[transaction.clone()], at(1), 5), 0);
+        assert_eq!(config.get_time_till_user_can_react(&[transaction], at(1), -5), 9 * 60);
+    }
+
+    #[test]
+    fn limits_without_sign_count_all_reactions() {
+        let config = CooldownConfig::new(2, 10);
+        let history = [reaction(0, 5, 5), reaction(1, -5, -5)];
+        assert_eq!(config.get_time_till_user_can_react(&history, at(2), 5), 8 * 60);
+    }
+
+    #[test]
+    fn settings_accept_a_limit_of_0() {
+        let settings: HashMap<String, String> = [("reaction_limit", "0"), ("bucket_size", "0"), ("positive_limit", "0"), ("negative_limit", "-1")]
+            .into_iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect();
+        let config = CooldownConfig::from_settings(&settings, 5, 10);
+        assert_eq!((config.reaction_limit, config.bucket_size, config.positive_limit, config.negative_limit), (0, 0, Some(0), None));
+        assert_eq!(config.get_time_till_user_can_react(&[reaction(0, 5, 5)], at(1), 5), 0);
+    }
+
+    #[test]
+    fn history_covers_the_limits_per_sign() {
+        let mut config = CooldownConfig::new(1, 10);
+        config.strategy = CooldownStrategy::Bucket;
+        config.bucket_size = 0;
+        config.bucket_refill_minutes = 10;
+        config.positive_limit = Some(3);
+        assert_eq!(config.history_span(), MINUTE * 30);
+    }
+}
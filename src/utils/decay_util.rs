@@ -0,0 +1,240 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+use rusqlite::Connection;
+use crate::data::bot_state::{delete_bot_state, get_bot_state, set_bot_state};
+use crate::data::room_setting::find_room_settings_in_db;
+use crate::data::transaction::{change_social_credit, Transaction};
+use crate::data::user_room_data::{find_all_room_ids_in_db, find_all_user_room_data_for_room_in_db};
+use crate::utils::score_util::ScoreBounds;
+
+const LAST_DECAY_KEY_PREFIX: &str = "last_decay:";
+
+/// Rooms without decay are checked again after this time, so enabling decay with !config takes effect without a restart
+const MAX_DECAY_CHECK_INTERVAL: Duration = Duration::from_secs(60 * 60);
+/// Ticks that were missed while the bot was offline are caught up on up to this number, older ones are skipped
+const MAX_MISSED_DECAY_TICKS: u64 = 24;
+/// How long a room waits after a failed tick until it is tried again
+const DECAY_RETRY_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+/// How the scores of a room move back to the baseline, decay is disabled if decay_interval_hours is not set
+pub struct DecayConfig {
+    pub interval: Duration,
+    pub percent: Option<i32>,
+    pub amount: Option<i32>,
+}
+
+impl DecayConfig {
+    pub fn from_settings(settings: &HashMap<String, String>) -> Option<Self> {
+        let get_number = |key: &str| settings.get(key).and_then(|value| value.parse::<i32>().ok()).filter(|value| *value > 0);
+        let interval_hours = get_number("decay_interval_hours")?;
+        let percent = get_number("decay_percent").map(|percent| percent.min(100));
+        let amount = get_number("decay_amount");
+        if percent.is_none() && amount.is_none() {
+            return None;
+        }
+
+        Some(DecayConfig {
+            interval: Duration::from_secs(interval_hours as u64 * 60 * 60),
+            percent,
+            amount,
+        })
+    }
+
+    /// The change of one decay tick for a user with social_credit, the percentage is used if both are set.
+    /// The score moves at least 1 per tick and never past the baseline, the change is saturated to the i32 range
+    pub fn get_decay_delta(&self, social_credit: i32, baseline: i32) -> i32 {
+        let distance = baseline as i64 - social_credit as i64;
+        let step = match (self.percent, self.amount) {
+            (Some(percent), _) => (distance.abs() * percent as i64 + 99) / 100,
+            (None, Some(amount)) => amount as i64,
+            (None, None) => 0,
+        };
+        (step.min(distance.abs()) * distance.signum()).clamp(i32::MIN as i64, i32::MAX as i64) as i32
+    }
+}
+
+/// Applies one decay tick to all users of the room, each change is recorded as a system transaction at the time of the tick.
+/// Every score is read and changed in its own database transaction, so reactions in between are not overwritten.
+/// Returns the number of changed scores
+pub fn apply_decay(conn: &Arc<Mutex<Connection>>, room_id: &str, config: &DecayConfig, baseline: i32, time: SystemTime) -> Result<usize, rusqlite::Error> {
+    let bounds = ScoreBounds::from_settings(&find_room_settings_in_db(conn, room_id));
+    let mut changed = 0;
+    for room_data in find_all_user_room_data_for_room_in_db(conn, room_id)? {
+        let mut record = Transaction::new(room_data.id, None, "decay", 0, 0, Some(String::from("Decay towards the baseline")));
+        record.time = time;
+        let result = change_social_credit(conn, &record, &bounds, |social_credit| {
+            let delta = config.get_decay_delta(social_credit, baseline);
+            Some(delta).filter(|delta| bounds.apply_change(social_credit, *delta).delta != 0)
+        })?;
+        if result.is_some() {
+            changed += 1;
+        }
+    }
+    Ok(changed)
+}
+
+/// Applies all decay ticks of the room that are due, including up to MAX_MISSED_DECAY_TICKS ticks that were missed
+/// while the bot was offline, so a long outage does not move every score to the baseline at once. Only ticks that
+/// succeeded are stored as done, a failed tick is tried again after DECAY_RETRY_INTERVAL.
+/// Returns the time of the next tick, or None if decay is disabled in the room
+fn run_due_decay_ticks(conn: &Arc<Mutex<Connection>>, room_id: &str, baseline: i32, now: SystemTime) -> Option<SystemTime> {
+    let key = format!("{}{}", LAST_DECAY_KEY_PREFIX, room_id);
+    let config = match DecayConfig::from_settings(&find_room_settings_in_db(conn, room_id)) {
+        Some(config) => config,
+        None => {
+            // Otherwise all ticks since decay was disabled would be applied at once when it is enabled again
+            if get_bot_state(conn, &key).is_some() {
+                let _ = delete_bot_state(conn, &key);
+            }
+            return None;
+        }
+    };
+
+    let last_tick = get_bot_state(conn, &key)
+        .and_then(|value| value.parse::<u64>().ok())
+        .map(|secs| SystemTime::UNIX_EPOCH + Duration::from_secs(secs));
+    let mut last_tick = match last_tick {
+        Some(last_tick) => last_tick,
+        None => {
+            // Decay was just enabled, the first tick is one interval from now
+            store_last_decay_tick(conn, &key, now);
+            return Some(now + config.interval);
+        }
+    };
+
+    let stored_tick = last_tick;
+    let interval_secs = config.interval.as_secs().max(1);
+    let missed = now.duration_since(last_tick).unwrap_or(Duration::ZERO).as_secs() / interval_secs;
+    if missed > MAX_MISSED_DECAY_TICKS {
+        let skipped = missed - MAX_MISSED_DECAY_TICKS;
+        last_tick += Duration::from_secs(skipped * interval_secs);
+        println!("Skipped {} missed decay ticks in room {}", skipped, room_id); // debug level
+    }
+
+    let mut ticks = 0;
+    let mut changed = 0;
+    let mut failed = false;
+    while last_tick + config.interval <= now {
+        let tick = last_tick + config.interval;
+        match apply_decay(conn, room_id, &config, baseline, tick) {
+            Ok(count) => changed += count,
+            Err(e) => {
+                println!("Decay in room {} failed: {}", room_id, e); // error level
+                failed = true;
+                break;
+            }
+        }
+        last_tick = tick;
+        ticks += 1;
+    }
+
+    if last_tick != stored_tick {
+        store_last_decay_tick(conn, &key, last_tick);
+    }
+    if ticks > 0 {
+        println!("Applied {} decay ticks in room {}, {} scores changed", ticks, room_id, changed); // debug level
+    }
+    if failed {
+        return Some(now + DECAY_RETRY_INTERVAL);
+    }
+    Some(last_tick + config.interval)
+}
+
+fn store_last_decay_tick(conn: &Arc<Mutex<Connection>>, key: &str, time: SystemTime) {
+    let secs = time.duration_since(SystemTime::UNIX_EPOCH).unwrap_or(Duration::ZERO).as_secs();
+    if let Err(e) = set_bot_state(conn, key, &secs.to_string()) {
+        println!("Unable to store the last decay tick: {}", e); // error level
+    }
+}
+
+/// Runs the decay of all rooms, the time of the last tick of each room is stored in the database
+/// so the schedule is kept across restarts and missed ticks are applied on start
+pub async fn run_decay_schedule(conn: Arc<Mutex<Connection>>, baseline: i32) {
+    loop {
+        let task_conn = conn.clone();
+        let next_tick = tokio::task::spawn_blocking(move || {
+            let now = SystemTime::now();
+            let room_ids = find_all_room_ids_in_db(&task_conn).unwrap_or_else(|e| {
+                println!("Unable to load rooms for decay: {}", e); // error level
+                Vec::new()
+            });
+            room_ids.iter()
+                .filter_map(|room_id| run_due_decay_ticks(&task_conn, room_id, baseline, now))
+                .min()
+        }).await.unwrap_or(None);
+
+        let wait = next_tick
+            .map(|next_tick| next_tick.duration_since(SystemTime::now()).unwrap_or(Duration::ZERO))
+            .unwrap_or(MAX_DECAY_CHECK_INTERVAL)
+            .min(MAX_DECAY_CHECK_INTERVAL);
+        tokio::time::sleep(wait).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::open_database;
+    use crate::data::room_setting::set_room_setting;
+    use crate::data::user::UserType;
+    use crate::utils::user_util::setup_user;
+
+    fn config(percent: Option<i32>, amount: Option<i32>) -> DecayConfig {
+        DecayConfig { interval: Duration::from_secs(60 * 60), percent, amount }
+    }
+
+    #[test]
+    fn decay_by_percent() {
+        assert_eq!(config(Some(10), None).get_decay_delta(200, 100), -10);
+        assert_eq!(config(Some(10), None).get_decay_delta(0, 100), 10);
+        // Rounded up, so small distances still move
+        assert_eq!(config(Some(10), None).get_decay_delta(105, 100), -1);
+        assert_eq!(config(Some(100), None).get_decay_delta(-50, 100), 150);
+    }
+
+    #[test]
+    fn decay_by_amount() {
+        assert_eq!(config(None, Some(20)).get_decay_delta(200, 100), -20);
+        assert_eq!(config(None, Some(20)).get_decay_delta(90, 100), 10);
+    }
+
+    #[test]
+    fn decay_prefers_the_percentage() {
+        assert_eq!(config(Some(50), Some(1)).get_decay_delta(200, 100), -50);
+    }
+
+    #[test]
+    fn decay_at_the_baseline() {
+        assert_eq!(config(Some(10), None).get_decay_delta(100, 100), 0);
+        assert_eq!(config(None, Some(10)).get_decay_delta(100, 100), 0);
+        assert_eq!(config(None, None).get_decay_delta(200, 100), 0);
+    }
+
+    #[test]
+    fn decay_saturates() {
+        assert_eq!(config(Some(100), None).get_decay_delta(i32::MIN, i32::MAX), i32::MAX);
+        assert_eq!(config(Some(100), None).get_decay_delta(i32::MAX, i32::MIN), i32::MIN);
+        assert_eq!(config(None, Some(i32::MAX)).get_decay_delta(i32::MAX, 0), -i32::MAX);
+    }
+
+    #[test]
+    fn missed_ticks_are_capped() {
+        let conn = Arc::new(Mutex::new(open_database(":memory:")));
+        let room_id = "!room:example.org";
+        set_room_setting(&conn, room_id, "decay_interval_hours", "1").unwrap();
+        set_room_setting(&conn, room_id, "decay_amount", "1").unwrap();
+        let user = setup_user(&conn, Some(room_id), &String::from("@bob:example.org"), UserType::Default, 1000).unwrap();
+
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let key = format!("{}{}", LAST_DECAY_KEY_PREFIX, room_id);
+        store_last_decay_tick(&conn, &key, now - Duration::from_secs(100 * 60 * 60));
+        assert_eq!(run_due_decay_ticks(&conn, room_id, 0, now), Some(now + Duration::from_secs(60 * 60)));
+
+        let room_data = find_all_user_room_data_for_room_in_db(&conn, room_id).unwrap();
+        assert_eq!(room_data[0].id, user.room_data.unwrap().id);
+        assert_eq!(room_data[0].social_credit, 1000 - MAX_MISSED_DECAY_TICKS as i32);
+        assert_eq!(get_bot_state(&conn, &key), Some(String::from("1700000000")));
+    }
+}
+
@@ -0,0 +1,472 @@
+use std::collections::BTreeSet;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use matrix_sdk::ruma::UserId;
+use rusqlite::{Connection, OptionalExtension, params};
+use serde::{Deserialize, Serialize};
+use serde::de::DeserializeOwned;
+
+/// Users are referenced by their full matrix id so the data can be moved between servers
+/// without depending on the database ids
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct ExportData {
+    pub users: Vec<ExportUser>,
+    pub user_room_data: Vec<ExportUserRoomData>,
+    pub emojis: Vec<ExportEmoji>,
+    pub reactions: Vec<ExportReaction>,
+    pub transactions: Vec<ExportTransaction>,
+    #[serde(default)] // Missing in exports of older versions
+    pub budgets: Vec<ExportBudget>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ExportUser {
+    pub user_id: String,
+    pub user_type: String,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ExportUserRoomData {
+    pub user_id: String,
+    pub room_id: String,
+    pub social_credit: i32,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ExportEmoji {
+    pub room_id: String,
+    pub emoji: String,
+    pub social_credit: i32,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ExportReaction {
+    pub user_id: String,
+    pub room_id: String,
+    pub time: i64,
+    pub message_event_id: String,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ExportTransaction {
+    pub user_id: String,
+    pub room_id: String,
+    pub sender_user_id: Option<String>,
+    pub kind: String,
+    pub delta: i32,
+    pub social_credit: i32,
+    pub reason: Option<String>,
+    pub time: i64,
+    #[serde(default)]
+    pub emoji_social_credit: Option<i32>,
+}
+
+/// The budget a user spent in the current period with the budget cooldown strategy
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ExportBudget {
+    pub user_id: String,
+    pub room_id: String,
+    pub period_start: i64,
+    pub spent: i64,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum ExportFormat {
+    Json,
+    Csv,
+}
+
+impl ExportFormat {
+    pub fn parse(text: &str) -> Option<ExportFormat> {
+        match text.to_lowercase().as_str() {
+            "json" => Some(ExportFormat::Json),
+            "csv" => Some(ExportFormat::Csv),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum ImportMode {
+    /// Keeps the existing data, imported values win on conflicts except for the roles of existing users,
+    /// an export from another server must not change who is an admin here
+    Merge,
+    /// Deletes all existing data of the imported rooms first, the roles of existing users are replaced with the imported ones
+    Replace,
+}
+
+#[derive(Default)]
+pub struct ImportReport {
+    pub dry_run: bool,
+    pub deleted: usize,
+    pub inserted: usize,
+    pub updated: usize,
+    pub skipped: usize,
+    pub conflicts: Vec<String>,
+}
+
+impl fmt::Display for ImportReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.dry_run {
+            writeln!(f, "Dry run, nothing was changed")?;
+        }
+        writeln!(f, "Deleted: {}, inserted: {}, updated: {}, skipped duplicates: {}", self.deleted, self.inserted, self.updated, self.skipped)?;
+        writeln!(f, "Conflicts: {}", self.conflicts.len())?;
+        for conflict in &self.conflicts {
+            writeln!(f, "- {}", conflict)?;
+        }
+        Ok(())
+    }
+}
+
+const CSV_USERS: &str = "users.csv";
+const CSV_USER_ROOM_DATA: &str = "user_room_data.csv";
+const CSV_EMOJIS: &str = "emojis.csv";
+const CSV_REACTIONS: &str = "reactions.csv";
+const CSV_TRANSACTIONS: &str = "transactions.csv";
+const CSV_BUDGETS: &str = "budgets.csv";
+
+fn user_type_to_string(user_type: i32) -> String {
+    match user_type {
+        1 => "moderator",
+        2 => "admin",
+        _ => "default",
+    }.to_string()
+}
+
+fn user_type_from_string(user_type: &str) -> i32 {
+    match user_type {
+        "moderator" => 1,
+        "admin" => 2,
+        _ => 0,
+    }
+}
+
+/// Collects all data of one room or of all rooms if room_id is None
+pub fn export_data(conn: &Connection, room_id: Option<&String>) -> Result<ExportData, rusqlite::Error> {
+    let room_filter = "(?1 IS NULL OR user_room_data.room_id = ?1)";
+    let mut data = ExportData::default();
+
+    let mut stmt = conn.prepare(
+        "SELECT DISTINCT user.mxid, user.user_type FROM user \
+         LEFT JOIN user_room_data ON user.id = user_room_data.user_id WHERE ?1 IS NULL OR user_room_data.room_id = ?1 ORDER BY user.id"
+    )?;
+    data.users = stmt.query_map(params![room_id], |row| {
+        Ok(ExportUser {
+            user_id: row.get(0)?,
+            user_type: user_type_to_string(row.get(1)?),
+        })
+    })?.collect::<Result<_, _>>()?;
+
+    let mut stmt = conn.prepare(&format!(
+        "SELECT user.mxid, user_room_data.room_id, user_room_data.social_credit FROM user_room_data \
+         INNER JOIN user ON user.id = user_room_data.user_id WHERE {} ORDER BY user_room_data.id", room_filter
+    ))?;
+    data.user_room_data = stmt.query_map(params![room_id], |row| {
+        Ok(ExportUserRoomData {
+            user_id: row.get(0)?,
+            room_id: row.get(1)?,
+            social_credit: row.get(2)?,
+        })
+    })?.collect::<Result<_, _>>()?;
+
+    let mut stmt = conn.prepare("SELECT room_id, emoji, social_credit FROM emoji WHERE ?1 IS NULL OR room_id = ?1 ORDER BY id")?;
+    data.emojis = stmt.query_map(params![room_id], |row| {
+        Ok(ExportEmoji {
+            room_id: row.get(0)?,
+            emoji: row.get(1)?,
+            social_credit: row.get(2)?,
+        })
+    })?.collect::<Result<_, _>>()?;
+
+    let mut stmt = conn.prepare(&format!(
+        "SELECT user.mxid, user_room_data.room_id, user_reaction.time, user_reaction.message_event_id FROM user_reaction \
+         INNER JOIN user_room_data ON user_room_data.id = user_reaction.user_room_data_id \
+         INNER JOIN user ON user.id = user_room_data.user_id WHERE {} ORDER BY user_reaction.id", room_filter
+    ))?;
+    data.reactions = stmt.query_map(params![room_id], |row| {
+        Ok(ExportReaction {
+            user_id: row.get(0)?,
+            room_id: row.get(1)?,
+            time: row.get(2)?,
+            message_event_id: row.get(3)?,
+        })
+    })?.collect::<Result<_, _>>()?;
+
+    let mut stmt = conn.prepare(&format!(
+        "SELECT user.mxid, user_room_data.room_id, sender.mxid, credit_transaction.kind, credit_transaction.delta, \
+         credit_transaction.social_credit, credit_transaction.reason, credit_transaction.time, credit_transaction.emoji_social_credit FROM credit_transaction \
+         INNER JOIN user_room_data ON user_room_data.id = credit_transaction.user_room_data_id \
+         INNER JOIN user ON user.id = user_room_data.user_id \
+         LEFT JOIN user AS sender ON sender.id = credit_transaction.sender_user_id WHERE {} ORDER BY credit_transaction.id", room_filter
+    ))?;
+    data.transactions = stmt.query_map(params![room_id], |row| {
+        Ok(ExportTransaction {
+            user_id: row.get(0)?,
+            room_id: row.get(1)?,
+            sender_user_id: row.get(2)?,
+            kind: row.get(3)?,
+            delta: row.get(4)?,
+            social_credit: row.get(5)?,
+            reason: row.get(6)?,
+            time: row.get(7)?,
+            emoji_social_credit: row.get(8)?,
+        })
+    })?.collect::<Result<_, _>>()?;
+
+    let mut stmt = conn.prepare(&format!(
+        "SELECT user.mxid, user_room_data.room_id, credit_budget.period_start, credit_budget.spent FROM credit_budget \
+         INNER JOIN user_room_data ON user_room_data.id = credit_budget.user_room_data_id \
+         INNER JOIN user ON user.id = user_room_data.user_id WHERE {} ORDER BY user_room_data.id", room_filter
+    ))?;
+    data.budgets = stmt.query_map(params![room_id], |row| {
+        Ok(ExportBudget {
+            user_id: row.get(0)?,
+            room_id: row.get(1)?,
+            period_start: row.get(2)?,
+            spent: row.get(3)?,
+        })
+    })?.collect::<Result<_, _>>()?;
+
+    Ok(data)
+}
+
+/// Serializes the data into files, a single file for json and one file per table for csv
+pub fn serialize_export_data(data: &ExportData, format: ExportFormat) -> Result<Vec<(String, Vec<u8>)>, String> {
+    match format {
+        ExportFormat::Json => {
+            let json = serde_json::to_vec_pretty(data).map_err(|e| e.to_string())?;
+            Ok(vec![(String::from("social_credit_export.json"), json)])
+        },
+        ExportFormat::Csv => Ok(vec![
+            (CSV_USERS.to_string(), to_csv(&data.users)?),
+            (CSV_USER_ROOM_DATA.to_string(), to_csv(&data.user_room_data)?),
+            (CSV_EMOJIS.to_string(), to_csv(&data.emojis)?),
+            (CSV_REACTIONS.to_string(), to_csv(&data.reactions)?),
+            (CSV_TRANSACTIONS.to_string(), to_csv(&data.transactions)?),
+            (CSV_BUDGETS.to_string(), to_csv(&data.budgets)?),
+        ]),
+    }
+}
+
+fn to_csv<T: Serialize>(rows: &[T]) -> Result<Vec<u8>, String> {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    for row in rows {
+        writer.serialize(row).map_err(|e| e.to_string())?;
+    }
+    writer.into_inner().map_err(|e| e.to_string())
+}
+
+/// Writes the export to path, which is a file for json and a directory for csv
+pub fn write_export_data(data: &ExportData, format: ExportFormat, path: &Path) -> Result<(), String> {
+    let files = serialize_export_data(data, format)?;
+    match format {
+        ExportFormat::Json => fs::write(path, &files[0].1).map_err(|e| e.to_string()),
+        ExportFormat::Csv => {
+            fs::create_dir_all(path).map_err(|e| e.to_string())?;
+            for (name, content) in files {
+                fs::write(path.join(name), content).map_err(|e| e.to_string())?;
+            }
+            Ok(())
+        },
+    }
+}
+
+/// Reads an export written by write_export_data, missing csv files are treated as empty tables
+pub fn read_export_data(format: ExportFormat, path: &Path) -> Result<ExportData, String> {
+    match format {
+        ExportFormat::Json => {
+            let json = fs::read(path).map_err(|e| e.to_string())?;
+            serde_json::from_slice(&json).map_err(|e| e.to_string())
+        },
+        ExportFormat::Csv => Ok(ExportData {
+            users: from_csv(&path.join(CSV_USERS))?,
+            user_room_data: from_csv(&path.join(CSV_USER_ROOM_DATA))?,
+            emojis: from_csv(&path.join(CSV_EMOJIS))?,
+            reactions: from_csv(&path.join(CSV_REACTIONS))?,
+            transactions: from_csv(&path.join(CSV_TRANSACTIONS))?,
+            budgets: from_csv(&path.join(CSV_BUDGETS))?,
+        }),
+    }
+}
+
+fn from_csv<T: DeserializeOwned>(path: &Path) -> Result<Vec<T>, String> {
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    let mut reader = csv::Reader::from_path(path).map_err(|e| e.to_string())?;
+    let rows: Result<Vec<T>, _> = reader.deserialize().collect();
+    rows.map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+/// Imports the data in a single database transaction, which is rolled back again for a dry run
+pub fn import_data(conn: &mut Connection, data: &ExportData, mode: ImportMode, dry_run: bool) -> Result<ImportReport, String> {
+    let tx = conn.transaction().map_err(|e| e.to_string())?;
+    let mut report = ImportReport { dry_run, ..Default::default() };
+
+    do_import_data(&tx, data, mode, &mut report).map_err(|e| e.to_string())?;
+
+    if dry_run {
+        tx.rollback().map_err(|e| e.to_string())?;
+    }
+    else {
+        tx.commit().map_err(|e| e.to_string())?;
+    }
+    Ok(report)
+}
+
+fn do_import_data(conn: &Connection, data: &ExportData, mode: ImportMode, report: &mut ImportReport) -> Result<(), String> {
+    if mode == ImportMode::Replace {
+        let room_ids: BTreeSet<&String> = data.user_room_data.iter().map(|d| &d.room_id)
+            .chain(data.emojis.iter().map(|e| &e.room_id))
+            .collect();
+        for room_id in room_ids {
+            let room_data_ids = "SELECT id FROM user_room_data WHERE room_id = ?1";
+            report.deleted += execute(conn, &format!("DELETE FROM credit_transaction WHERE user_room_data_id IN ({})", room_data_ids), params![room_id])?;
+            report.deleted += execute(conn, &format!("DELETE FROM user_reaction WHERE user_room_data_id IN ({})", room_data_ids), params![room_id])?;
+            report.deleted += execute(conn, &format!("DELETE FROM credit_budget WHERE user_room_data_id IN ({})", room_data_ids), params![room_id])?;
+            report.deleted += execute(conn, "DELETE FROM user_room_data WHERE room_id = ?1", params![room_id])?;
+            report.deleted += execute(conn, "DELETE FROM emoji WHERE room_id = ?1", params![room_id])?;
+        }
+    }
+
+    for user in &data.users {
+        let user_type = user_type_from_string(&user.user_type);
+        let (id, existing_type) = import_user(conn, &user.user_id, user_type, report)?;
+        if existing_type == user_type {
+            continue;
+        }
+        if mode == ImportMode::Merge {
+            report.conflicts.push(format!("{}: user type {} kept, the import has {}", user.user_id, user_type_to_string(existing_type), user.user_type));
+            continue;
+        }
+        report.conflicts.push(format!("{}: user type {} replaced with {}", user.user_id, user_type_to_string(existing_type), user.user_type));
+        execute(conn, "UPDATE user SET user_type = ?1 WHERE id = ?2", params![user_type, id])?;
+        report.updated += 1;
+    }
+
+    for room_data in &data.user_room_data {
+        let (user_id, _) = import_user(conn, &room_data.user_id, 0, report)?;
+        let existing: Option<i32> = query_optional(conn, "SELECT social_credit FROM user_room_data WHERE user_id = ?1 AND room_id = ?2", params![user_id, room_data.room_id])?;
+        match existing {
+            None => {
+                execute(conn, "INSERT INTO user_room_data (user_id, room_id, social_credit) VALUES (?1, ?2, ?3)", params![user_id, room_data.room_id, room_data.social_credit])?;
+                report.inserted += 1;
+            },
+            Some(social_credit) if social_credit == room_data.social_credit => report.skipped += 1,
+            Some(social_credit) => {
+                report.conflicts.push(format!("{} in {}: social credit {} replaced with {}", room_data.user_id, room_data.room_id, social_credit, room_data.social_credit));
+                execute(conn, "UPDATE user_room_data SET social_credit = ?1 WHERE user_id = ?2 AND room_id = ?3", params![room_data.social_credit, user_id, room_data.room_id])?;
+                report.updated += 1;
+            },
+        }
+    }
+
+    for emoji in &data.emojis {
+        let existing: Option<i32> = query_optional(conn, "SELECT social_credit FROM emoji WHERE room_id = ?1 AND emoji = ?2", params![emoji.room_id, emoji.emoji])?;
+        match existing {
+            None => {
+                execute(conn, "INSERT INTO emoji (room_id, emoji, social_credit) VALUES (?1, ?2, ?3)", params![emoji.room_id, emoji.emoji, emoji.social_credit])?;
+                report.inserted += 1;
+            },
+            Some(social_credit) if social_credit == emoji.social_credit => report.skipped += 1,
+            Some(social_credit) => {
+                report.conflicts.push(format!("Emoji {} in {}: social credit {} replaced with {}", emoji.emoji, emoji.room_id, social_credit, emoji.social_credit));
+                execute(conn, "UPDATE emoji SET social_credit = ?1 WHERE room_id = ?2 AND emoji = ?3", params![emoji.social_credit, emoji.room_id, emoji.emoji])?;
+                report.updated += 1;
+            },
+        }
+    }
+
+    for reaction in &data.reactions {
+        let room_data_id = import_user_room_data(conn, &reaction.user_id, &reaction.room_id, report)?;
+        let exists: Option<i32> = query_optional(conn, "SELECT id FROM user_reaction WHERE user_room_data_id = ?1 AND time = ?2 AND message_event_id = ?3", params![room_data_id, reaction.time, reaction.message_event_id])?;
+        if exists.is_some() {
+            report.skipped += 1;
+            continue;
+        }
+        execute(conn, "INSERT INTO user_reaction (user_room_data_id, time, message_event_id) VALUES (?1, ?2, ?3)", params![room_data_id, reaction.time, reaction.message_event_id])?;
+        report.inserted += 1;
+    }
+
+    for transaction in &data.transactions {
+        let room_data_id = import_user_room_data(conn, &transaction.user_id, &transaction.room_id, report)?;
+        let sender_id = match &transaction.sender_user_id {
+            Some(sender) => Some(import_user(conn, sender, 0, report)?.0),
+            None => None,
+        };
+        let exists: Option<i32> = query_optional(
+            conn,
+            "SELECT id FROM credit_transaction WHERE user_room_data_id = ?1 AND time = ?2 AND kind = ?3 AND delta = ?4 AND social_credit = ?5",
+            params![room_data_id, transaction.time, transaction.kind, transaction.delta, transaction.social_credit]
+        )?;
+        if exists.is_some() {
+            report.skipped += 1;
+            continue;
+        }
+        execute(
+            conn,
+            "INSERT INTO credit_transaction (user_room_data_id, sender_user_id, kind, delta, social_credit, reason, time, emoji_social_credit) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            params![room_data_id, sender_id, transaction.kind, transaction.delta, transaction.social_credit, transaction.reason, transaction.time, transaction.emoji_social_credit]
+        )?;
+        report.inserted += 1;
+    }
+
+    for budget in &data.budgets {
+        let room_data_id = import_user_room_data(conn, &budget.user_id, &budget.room_id, report)?;
+        let existing: Option<(i64, i64)> = conn.query_row("SELECT period_start, spent FROM credit_budget WHERE user_room_data_id = ?1", params![room_data_id], |row| Ok((row.get(0)?, row.get(1)?)))
+            .optional()
+            .map_err(|e| e.to_string())?;
+        match existing {
+            None => {
+                execute(conn, "INSERT INTO credit_budget (user_room_data_id, period_start, spent) VALUES (?1, ?2, ?3)", params![room_data_id, budget.period_start, budget.spent])?;
+                report.inserted += 1;
+            },
+            Some(existing) if existing == (budget.period_start, budget.spent) => report.skipped += 1,
+            Some(_) => {
+                execute(conn, "UPDATE credit_budget SET period_start = ?1, spent = ?2 WHERE user_room_data_id = ?3", params![budget.period_start, budget.spent, room_data_id])?;
+                report.updated += 1;
+            },
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns the id and the current user type of the user, creates the user if it does not exist yet
+fn import_user(conn: &Connection, user_id: &str, user_type: i32, report: &mut ImportReport) -> Result<(i32, i32), String> {
+    let user_id = UserId::parse(user_id).map_err(|e| format!("Invalid user id {}: {}", user_id, e))?;
+    let existing: Option<(i32, i32)> = conn.query_row("SELECT id, user_type FROM user WHERE mxid = ?1", params![user_id.as_str()], |row| Ok((row.get(0)?, row.get(1)?)))
+        .optional()
+        .map_err(|e| e.to_string())?;
+    if let Some(existing) = existing {
+        return Ok(existing);
+    }
+
+    execute(conn, "INSERT INTO user (name, url, user_type, mxid) VALUES (?1, ?2, ?3, ?4)", params![user_id.localpart(), user_id.server_name().as_str(), user_type, user_id.as_str()])?;
+    report.inserted += 1;
+    Ok((conn.last_insert_rowid() as i32, user_type))
+}
+
+/// Returns the id of the room data, reactions and transactions of users without room data in the import get a room data entry with 0 social credit
+fn import_user_room_data(conn: &Connection, user_id: &str, room_id: &String, report: &mut ImportReport) -> Result<i32, String> {
+    let (user_db_id, _) = import_user(conn, user_id, 0, report)?;
+    let existing: Option<i32> = query_optional(conn, "SELECT id FROM user_room_data WHERE user_id = ?1 AND room_id = ?2", params![user_db_id, room_id])?;
+    if let Some(id) = existing {
+        return Ok(id);
+    }
+
+    report.conflicts.push(format!("{} in {}: history without room data, created with 0 social credit", user_id, room_id));
+    execute(conn, "INSERT INTO user_room_data (user_id, room_id, social_credit) VALUES (?1, ?2, 0)", params![user_db_id, room_id])?;
+    report.inserted += 1;
+    Ok(conn.last_insert_rowid() as i32)
+}
+
+fn execute<P: rusqlite::Params>(conn: &Connection, sql: &str, params: P) -> Result<usize, String> {
+    conn.execute(sql, params).map_err(|e| e.to_string())
+}
+
+fn query_optional<T: rusqlite::types::FromSql, P: rusqlite::Params>(conn: &Connection, sql: &str, params: P) -> Result<Option<T>, String> {
+    conn.query_row(sql, params, |row| row.get(0)).optional().map_err(|e| e.to_string())
+}
@@ -0,0 +1,78 @@
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+use rusqlite::Connection;
+use crate::config::MaintenanceConfig;
+use crate::data::BUSY_TIMEOUT;
+use crate::data::bot_state::{get_bot_state, set_bot_state};
+use crate::data::event::cleanup_table_event;
+use crate::data::user_reaction::cleanup_table_user_reaction;
+
+const LAST_MAINTENANCE_KEY: &str = "last_maintenance";
+
+pub struct MaintenanceReport {
+    pub deleted_events: usize,
+    pub deleted_reactions: usize,
+}
+
+/// Deletes handled events that are older than the retention window, events that old are not part of
+/// the timeline the bot receives on sync anymore, so they cannot be handled twice.
+/// Reactions are only needed to detect a second reaction to the same message, the cooldowns use credit_transaction.
+/// They are deleted after the reaction retention, reactions to messages older than that are ignored.
+/// The score history in credit_transaction is never pruned
+pub fn run_maintenance(conn: &Connection, config: &MaintenanceConfig) -> Result<MaintenanceReport, rusqlite::Error> {
+    let now = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap_or(Duration::from_secs(0)).as_secs() as i64;
+    let event_retention_secs = config.event_retention_days as i64 * 24 * 60 * 60;
+    let reaction_retention_secs = config.reaction_retention_days as i64 * 24 * 60 * 60;
+
+    let deleted_events = cleanup_table_event(conn, now - event_retention_secs)?;
+    let deleted_reactions = cleanup_table_user_reaction(conn, now - reaction_retention_secs)?;
+
+    Ok(MaintenanceReport {
+        deleted_events,
+        deleted_reactions,
+    })
+}
+
+/// Runs ANALYZE and VACUUM on a separate connection, so the shared connection is not locked while the database is rebuilt.
+/// VACUUM still locks the whole database, everything else that uses it waits for up to the busy timeout until it is done
+pub fn optimize_database(db_path: &str) -> Result<(), rusqlite::Error> {
+    let conn = Connection::open(db_path)?;
+    conn.busy_timeout(BUSY_TIMEOUT)?;
+    conn.execute_batch("ANALYZE; VACUUM;")
+}
+
+/// Runs the maintenance every interval_hours, the time of the last run is stored in the database
+/// so the schedule is kept across restarts
+pub async fn run_maintenance_schedule(conn: Arc<Mutex<Connection>>, db_path: String, config: MaintenanceConfig) {
+    let interval = Duration::from_secs(config.interval_hours.max(1) * 60 * 60);
+    loop {
+        let last_run = get_bot_state(&conn, LAST_MAINTENANCE_KEY)
+            .and_then(|value| value.parse::<u64>().ok())
+            .map(|secs| SystemTime::UNIX_EPOCH + Duration::from_secs(secs));
+        let wait = match last_run {
+            Some(last_run) => (last_run + interval).duration_since(SystemTime::now()).unwrap_or(Duration::ZERO),
+            None => Duration::ZERO,
+        };
+        tokio::time::sleep(wait).await;
+
+        let task_conn = conn.clone();
+        let task_config = config.clone();
+        let task_db_path = db_path.clone();
+        let result = tokio::task::spawn_blocking(move || {
+            let report = run_maintenance(&task_conn.lock().unwrap(), &task_config)?;
+            optimize_database(&task_db_path)?;
+            Ok::<MaintenanceReport, rusqlite::Error>(report)
+        }).await;
+
+        match result {
+            Ok(Ok(report)) => println!("Database maintenance done, deleted {} handled events and {} reactions", report.deleted_events, report.deleted_reactions),
+            Ok(Err(e)) => println!("Database maintenance failed: {}", e), // error level
+            Err(e) => println!("Database maintenance failed: {}", e), // error level
+        }
+
+        let now = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap_or(Duration::from_secs(0)).as_secs();
+        if let Err(e) = set_bot_state(&conn, LAST_MAINTENANCE_KEY, &now.to_string()) {
+            println!("Unable to store the time of the last maintenance: {}", e); // error level
+        }
+    }
+}
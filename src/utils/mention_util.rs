@@ -0,0 +1,99 @@
+use matrix_sdk::room::Joined;
+use matrix_sdk::ruma::{OwnedUserId, UserId};
+use percent_encoding::percent_decode_str;
+use serde_json::{json, Value};
+use crate::web::pages::escape_html;
+
+/// A message that shows users as matrix.to pills with their display name. Whether the mentioned users
+/// are pinged is decided when the content is built, through the m.mentions metadata
+#[derive(Default)]
+pub struct MentionMessage {
+    pub text: String,
+    pub html: String,
+    mentioned_user_ids: Vec<OwnedUserId>,
+}
+
+impl MentionMessage {
+    /// The html must already be escaped
+    pub fn push(&mut self, text: &str, html: &str) {
+        self.text.push_str(text);
+        self.html.push_str(html);
+    }
+
+    pub fn push_mention(&mut self, user_id: &UserId, display_name: &str) {
+        self.text.push_str(display_name);
+        self.html.push_str(&format!("<a href=\"https://matrix.to/#/{}\">{}</a>", user_id, escape_html(display_name)));
+        if !self.mentioned_user_ids.iter().any(|mentioned| mentioned == user_id) {
+            self.mentioned_user_ids.push(user_id.to_owned());
+        }
+    }
+
+    /// Only the users in ping are notified, clients that support m.mentions do not ping
+    /// anyone just because their name appears in the body
+    pub fn to_content(&self, msgtype: &str, ping: &[&UserId]) -> Value {
+        let user_ids: Vec<&OwnedUserId> = self.mentioned_user_ids.iter()
+            .filter(|user_id| ping.contains(&user_id.as_ref()))
+            .collect();
+        json!({
+            "msgtype": msgtype,
+            "body": self.text,
+            "format": "org.matrix.custom.html",
+            "formatted_body": self.html,
+            "m.mentions": { "user_ids": user_ids },
+        })
+    }
+
+    pub async fn send(&self, room: &Joined, ping: &[&UserId]) {
+        if let Err(e) = room.send_raw(self.to_content("m.text", ping), "m.room.message", None).await {
+            println!("Unable to send message to room {}: {}", room.room_id(), e); // error level
+        }
+    }
+}
+
+/// The first user that is mentioned with a matrix.to pill in the html of a message
+pub fn find_mentioned_user_id(formatted_body: &str) -> Option<OwnedUserId> {
+    formatted_body.split("https://matrix.to/#/").skip(1).find_map(|link| {
+        let link = &link[..link.find(['"', '\'', '?']).unwrap_or(link.len())];
+        UserId::parse(percent_decode_str(link).decode_utf8().ok()?.as_ref()).ok()
+    })
+}
+
+/// The first user that is mentioned with a matrix.to pill and the plain text after the pill, so the arguments after
+/// the pill can be parsed without mistaking parts of the display name in the pill for them
+pub fn find_mentioned_user_id_and_following_text(formatted_body: &str) -> Option<(OwnedUserId, String)> {
+    formatted_body.match_indices("https://matrix.to/#/").find_map(|(index, prefix)| {
+        let link = &formatted_body[index + prefix.len()..];
+        let link_end = link.find(['"', '\'', '?']).unwrap_or(link.len());
+        let user_id = UserId::parse(percent_decode_str(&link[..link_end]).decode_utf8().ok()?.as_ref()).ok()?;
+        let pill_end = link.find("</a>")? + "</a>".len();
+        Some((user_id, html_to_text(&link[pill_end..])))
+    })
+}
+
+/// Removes the tags of the html and decodes the entities that html_escape of clients produce
+fn html_to_text(html: &str) -> String {
+    let mut text = String::new();
+    let mut in_tag = false;
+    for character in html.chars() {
+        match character {
+            '<' => in_tag = true,
+            '>' if in_tag => {
+                in_tag = false;
+                text.push(' ');
+            },
+            _ if !in_tag => text.push(character),
+            _ => {},
+        }
+    }
+    text.replace("&lt;", "<").replace("&gt;", ">").replace("&quot;", "\"").replace("&#39;", "'").replace("&amp;", "&")
+}
+
+/// The display name of the user in the room, or the localpart if the user has none.
+/// The matrix id is added if another member of the room uses the same display name
+pub async fn get_display_name(room: &Joined, user_id: &UserId) -> String {
+    match room.get_member_no_sync(user_id).await {
+        Ok(Some(member)) if member.name_ambiguous() => format!("{} ({})", member.name(), user_id),
+        Ok(Some(member)) => member.name().to_string(),
+        _ => user_id.localpart().to_string(),
+    }
+}
@@ -1,3 +1,17 @@
 pub mod user_util;
 pub mod autojoin;
 pub mod emoji_util;
+pub mod export_util;
+pub mod backup_util;
+pub mod maintenance_util;
+pub mod cooldown_util;
+pub mod room_config_util;
+pub mod notice_util;
+pub mod mention_util;
+pub mod decay_util;
+pub mod rank_util;
+pub mod consequence_util;
+pub mod score_util;
+pub mod collusion_util;
+pub mod budget_util;
+pub mod season_util;
@@ -0,0 +1,130 @@
+use std::time::Duration;
+use matrix_sdk::Client;
+use matrix_sdk::room::Joined;
+use matrix_sdk::ruma::{EventId, OwnedUserId, UserId};
+use matrix_sdk::ruma::api::client::room::create_room::v3::{Request as CreateRoomRequest, RoomPreset};
+use matrix_sdk::ruma::events::direct::DirectEventContent;
+use matrix_sdk::ruma::events::reaction::{ReactionEventContent, Relation};
+use matrix_sdk::ruma::events::room::message::RoomMessageEventContent;
+use serde_json::json;
+use crate::utils::mention_util::MentionMessage;
+
+const NOTICE_REACTION: &str = "⏳";
+
+/// How messages that are only meant for a single user, like cooldown notices, are delivered
+#[derive(Clone, PartialEq)]
+pub enum NoticeMode {
+    /// A normal message in the room
+    Room,
+    /// A m.notice in the room, most clients show these less prominently
+    Notice,
+    /// A reply in a thread on the message that was reacted to
+    Thread,
+    /// A direct message to the user
+    Dm,
+    /// A ⏳ reaction on the message that was reacted to
+    Reaction,
+    Silent,
+}
+
+impl NoticeMode {
+    pub fn parse(text: &str) -> Option<Self> {
+        match text.to_lowercase().as_str() {
+            "room" => Some(NoticeMode::Room),
+            "notice" => Some(NoticeMode::Notice),
+            "thread" => Some(NoticeMode::Thread),
+            "dm" => Some(NoticeMode::Dm),
+            "reaction" => Some(NoticeMode::Reaction),
+            "silent" => Some(NoticeMode::Silent),
+            _ => None,
+        }
+    }
+}
+
+/// Sends a notice to a single user the way the room chose, target_event_id is the message the notice is about.
+/// Thread and reaction fall back to a m.notice in the room if there is no such message.
+/// The user is only pinged if ping is set
+pub async fn send_user_notice(room: &Joined, mode: &NoticeMode, user_id: &UserId, target_event_id: Option<&EventId>, message: MentionMessage, ping: bool) {
+    let ping_user_ids = if ping { vec![user_id] } else { Vec::new() };
+    let result = match (mode, target_event_id) {
+        (NoticeMode::Silent, _) => return,
+        (NoticeMode::Room, _) => room.send_raw(message.to_content("m.text", &ping_user_ids), "m.room.message", None).await.map(|_| ()),
+        (NoticeMode::Thread, Some(target_event_id)) => {
+            let mut content = message.to_content("m.notice", &ping_user_ids);
+            content["m.relates_to"] = json!({
+                "rel_type": "m.thread",
+                "event_id": target_event_id,
+                "is_falling_back": true,
+                "m.in_reply_to": { "event_id": target_event_id },
+            });
+            room.send_raw(content, "m.room.message", None).await.map(|_| ())
+        },
+        (NoticeMode::Reaction, Some(target_event_id)) => {
+            let content = ReactionEventContent::new(Relation::new(target_event_id.to_owned(), NOTICE_REACTION.to_string()));
+            room.send(content, None).await.map(|_| ())
+        },
+        (NoticeMode::Dm, _) => {
+            // Creating the room can take until the next sync, so it must not block the event handler
+            let client = room.client();
+            let user_id = user_id.to_owned();
+            tokio::spawn(async move {
+                send_direct_message(&client, user_id, message.text).await;
+            });
+            return;
+        },
+        _ => room.send_raw(message.to_content("m.notice", &ping_user_ids), "m.room.message", None).await.map(|_| ()),
+    };
+
+    if let Err(e) = result {
+        println!("Unable to send notice to {} in room {}: {}", user_id, room.room_id(), e); // error level
+    }
+}
+
+async fn send_direct_message(client: &Client, user_id: OwnedUserId, text: String) {
+    let room = match get_or_create_dm_room(client, user_id.clone()).await {
+        Ok(room) => room,
+        Err(e) => {
+            println!("Unable to get a direct message room with {}: {}", user_id, e); // error level
+            return;
+        }
+    };
+
+    if let Err(e) = room.send(RoomMessageEventContent::notice_plain(text), None).await {
+        println!("Unable to send direct message to {}: {}", user_id, e); // error level
+    }
+}
+
+/// Returns the room the bot shares only with the user, a new one is created and marked as direct message if there is none
+async fn get_or_create_dm_room(client: &Client, user_id: OwnedUserId) -> anyhow::Result<Joined> {
+    let existing_room = client.joined_rooms().into_iter().find(|room| {
+        let targets = room.direct_targets();
+        targets.len() == 1 && targets.contains(&user_id)
+    });
+    if let Some(room) = existing_room {
+        return Ok(room);
+    }
+
+    let invite = [user_id.clone()];
+    let mut request = CreateRoomRequest::new();
+    request.invite = &invite;
+    request.is_direct = true;
+    request.preset = Some(RoomPreset::TrustedPrivateChat);
+    let response = client.create_room(request).await?;
+
+    // Without m.direct the room would not be found again and a new one would be created for every notice
+    let mut direct_rooms = client.account().account_data::<DirectEventContent>().await?
+        .map(|content| content.deserialize())
+        .transpose()?
+        .unwrap_or_default();
+    direct_rooms.entry(user_id).or_default().push(response.room_id.clone());
+    client.account().set_account_data(direct_rooms).await?;
+
+    // The room is only known to the client after the next sync
+    for _ in 0..30 {
+        if let Some(room) = client.get_joined_room(&response.room_id) {
+            return Ok(room);
+        }
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    }
+    anyhow::bail!("Room {} was created but not synced", response.room_id)
+}
@@ -0,0 +1,117 @@
+use std::sync::{Arc, Mutex};
+use matrix_sdk::ruma::UserId;
+use rusqlite::Connection;
+use crate::data::rank::{find_all_ranks_for_room_in_db, Rank};
+use crate::data::user::HtmlAndTextAnswer;
+use crate::utils::mention_util::MentionMessage;
+use crate::web::pages::escape_html;
+
+/// Parses the score range of a rank: >=1000, >999, <0, <=-1 or 0..1000 where the end is exclusive
+pub fn parse_rank_range(text: &str) -> Option<(Option<i32>, Option<i32>)> {
+    let parse = |number: &str| number.trim().parse::<i32>().ok();
+    if let Some(number) = text.strip_prefix(">=") {
+        return Some((Some(parse(number)?), None));
+    }
+    if let Some(number) = text.strip_prefix("<=") {
+        return Some((None, Some(parse(number)?.checked_add(1)?)));
+    }
+    if let Some(number) = text.strip_prefix('>') {
+        return Some((Some(parse(number)?.checked_add(1)?), None));
+    }
+    if let Some(number) = text.strip_prefix('<') {
+        return Some((None, Some(parse(number)?)));
+    }
+    let (min, max) = text.split_once("..")?;
+    let (min, max) = (parse(min)?, parse(max)?);
+    if min >= max {
+        return None;
+    }
+    Some((Some(min), Some(max)))
+}
+
+pub fn format_rank_range(rank: &Rank) -> String {
+    match (rank.min_social_credit, rank.max_social_credit) {
+        (Some(min), Some(max)) => format!("{}..{}", min, max),
+        (Some(min), None) => format!(">={}", min),
+        (None, Some(max)) => format!("<{}", max),
+        (None, None) => String::from("any score"),
+    }
+}
+
+/// The rank of a user with the social credit, ranks have to be sorted highest first
+/// so the higher rank wins if ranges overlap
+pub fn get_rank(ranks: &[Rank], social_credit: i32) -> Option<&Rank> {
+    ranks.iter().find(|rank| rank.contains(social_credit))
+}
+
+/// Appends a line announcing the new rank if the change of the social credit moved the user across a rank boundary
+pub fn push_rank_change(message: &mut MentionMessage, ranks: &[Rank], user_id: &UserId, display_name: &str, old_social_credit: i32, new_social_credit: i32) {
+    let old_rank = get_rank(ranks, old_social_credit);
+    let new_rank = get_rank(ranks, new_social_credit);
+    if old_rank.map(|rank| rank.id) == new_rank.map(|rank| rank.id) {
+        return;
+    }
+
+    message.push("\n", "<br>");
+    message.push_mention(user_id, display_name);
+    match (new_rank, old_rank) {
+        (Some(new_rank), _) => message.push(&format!(" is now {}", new_rank.title), &format!(" is now <b>{}</b>", escape_html(&new_rank.title))),
+        (None, Some(old_rank)) => message.push(&format!(" lost the rank {}", old_rank.title), &format!(" lost the rank <b>{}</b>", escape_html(&old_rank.title))),
+        (None, None) => {},
+    }
+}
+
+pub fn get_rank_list_answer(conn: &Arc<Mutex<Connection>>, room_id: &str) -> HtmlAndTextAnswer {
+    let ranks = find_all_ranks_for_room_in_db(conn, room_id).unwrap_or_default();
+    if ranks.is_empty() {
+        return HtmlAndTextAnswer {
+            html: String::from("No ranks, use the !help command to see how to add ranks"),
+            text: String::from("No ranks, use the !help command to see how to add ranks"),
+        };
+    }
+
+    let mut text_body = String::from("Ranks:");
+    let mut html_body = String::from("<h3>Ranks:</h3>");
+    for rank in ranks {
+        text_body.push_str(&format!("\n{}: {}", rank.title, format_rank_range(&rank)));
+        html_body.push_str(&format!("<br><b>{}</b>: {}", escape_html(&rank.title), format_rank_range(&rank)));
+    }
+
+    HtmlAndTextAnswer {
+        html: html_body,
+        text: text_body,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_open_ranges() {
+        assert_eq!(parse_rank_range(">=100"), Some((Some(100), None)));
+        assert_eq!(parse_rank_range(">100"), Some((Some(101), None)));
+        assert_eq!(parse_rank_range("<=100"), Some((None, Some(101))));
+        assert_eq!(parse_rank_range("<100"), Some((None, Some(100))));
+        assert_eq!(parse_rank_range("<-5"), Some((None, Some(-5))));
+    }
+
+    #[test]
+    fn parse_closed_ranges() {
+        assert_eq!(parse_rank_range("0..100"), Some((Some(0), Some(100))));
+        assert_eq!(parse_rank_range("-100..-1"), Some((Some(-100), Some(-1))));
+        assert_eq!(parse_rank_range("5..5"), None);
+        assert_eq!(parse_rank_range("10..5"), None);
+    }
+
+    #[test]
+    fn parse_invalid_ranges() {
+        assert_eq!(parse_rank_range(""), None);
+        assert_eq!(parse_rank_range("100"), None);
+        assert_eq!(parse_rank_range(">="), None);
+        assert_eq!(parse_rank_range("a..b"), None);
+        // The exclusive bound of these would not fit into an i32
+        assert_eq!(parse_rank_range(">2147483647"), None);
+        assert_eq!(parse_rank_range("<=2147483647"), None);
+    }
+}
@@ -0,0 +1,109 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use rusqlite::Connection;
+use crate::data::room_setting::find_room_settings_in_db;
+use crate::data::user::HtmlAndTextAnswer;
+use crate::utils::collusion_util::CollusionAction;
+use crate::utils::cooldown_util::CooldownStrategy;
+use crate::utils::notice_util::NoticeMode;
+use crate::utils::score_util::ReactionWeights;
+
+/// A setting that can be changed per room with !config
+pub struct RoomSetting {
+    pub key: &'static str,
+    pub description: &'static str,
+    validate: fn(&str) -> bool,
+}
+
+pub const ROOM_SETTINGS: &[RoomSetting] = &[
+    RoomSetting { key: "cooldown_strategy", description: "window, bucket or budget", validate: |value| CooldownStrategy::parse(value).is_some() },
+    RoomSetting { key: "reaction_limit", description: "Reactions per reaction_timespan with the window strategy, 0 for no limit", validate: is_non_negative_number },
+    RoomSetting { key: "reaction_timespan", description: "Minutes of the window", validate: is_positive_number },
+    RoomSetting { key: "bucket_size", description: "Reactions that can be used at once with the bucket strategy, 0 for no limit", validate: is_non_negative_number },
+    RoomSetting { key: "bucket_refill_minutes", description: "Minutes until the bucket gains another reaction", validate: is_positive_number },
+    RoomSetting { key: "daily_budget", description: "Absolute social credit a user can hand out per day with the budget strategy, default: 100", validate: is_positive_number },
+    RoomSetting { key: "budget_reset_hour", description: "Hour of the day in UTC when the budgets are reset, default: 0", validate: |value| value.parse::<u32>().is_ok_and(|value| value < 24) },
+    RoomSetting { key: "positive_limit", description: "Separate limit for emojis with a positive social credit, 0 for no limit", validate: is_non_negative_number },
+    RoomSetting { key: "negative_limit", description: "Separate limit for emojis with a negative social credit, 0 for no limit", validate: is_non_negative_number },
+    RoomSetting { key: "recipient_daily_limit", description: "How often a user can change the score of the same user per day", validate: is_positive_number },
+    RoomSetting { key: "cooldown_notice", description: "How users are told that they are on cooldown: room, notice, thread, dm, reaction or silent", validate: |value| NoticeMode::parse(value).is_some() },
+    RoomSetting { key: "cooldown_notice_interval", description: "Minutes until the same user is told again that they are on cooldown, 0 to tell them every time", validate: is_number },
+    RoomSetting { key: "decay_interval_hours", description: "Hours between two decay steps that move every score towards the initial social credit, decay is off if not set", validate: is_positive_number },
+    RoomSetting { key: "decay_percent", description: "Percent of the distance to the initial social credit a score moves per decay step", validate: |value| value.parse::<i32>().is_ok_and(|value| (1..=100).contains(&value)) },
+    RoomSetting { key: "decay_amount", description: "Fixed amount a score moves per decay step, only used if decay_percent is not set", validate: is_positive_number },
+    RoomSetting { key: "score_min", description: "Lowest possible score, no limit if not set", validate: is_integer },
+    RoomSetting { key: "score_max", description: "Highest possible score, no limit if not set", validate: is_integer },
+    RoomSetting { key: "max_change", description: "Largest change of a score by a single reaction, no limit if not set", validate: is_positive_number },
+    RoomSetting { key: "reaction_weights", description: "Multipliers for reactions by the score of the reacting user as range=multiplier pairs, e.g. <0=0.5,>=1000=1.5, off if not set", validate: |value| ReactionWeights::parse(value).is_some() },
+    RoomSetting { key: "collusion_action", description: "What happens to users that farm each other's score: off, flag to only list them with !suspicious or dampen to also reduce their reactions to each other, default: flag", validate: |value| CollusionAction::parse(value).is_some() },
+    RoomSetting { key: "collusion_window_days", description: "Days of reactions that are analysed for collusion", validate: is_positive_number },
+    RoomSetting { key: "collusion_min_reactions", description: "Reactions both users of a pair need to have given each other to be suspicious", validate: is_positive_number },
+    RoomSetting { key: "collusion_mutual_percent", description: "Percent of all reactions the two users gave that went to each other to be suspicious", validate: |value| value.parse::<i32>().is_ok_and(|value| (1..=100).contains(&value)) },
+    RoomSetting { key: "collusion_burst_reactions", description: "Reactions to one user within collusion_burst_minutes that are suspicious if they came from a small group", validate: is_positive_number },
+    RoomSetting { key: "collusion_burst_minutes", description: "Minutes of a burst of reactions", validate: is_positive_number },
+    RoomSetting { key: "collusion_clique_size", description: "Largest group of users whose burst of reactions is suspicious", validate: is_positive_number },
+    RoomSetting { key: "collusion_dampen_percent", description: "Percent of the normal change that suspicious reactions still apply with the dampen action", validate: |value| value.parse::<i32>().is_ok_and(|value| (0..=100).contains(&value)) },
+    RoomSetting { key: "transfer_floor", description: "Lowest score a user can reach by giving social credit away with !give, default: 0", validate: is_integer },
+    RoomSetting { key: "transfer_limit", description: "Transfers with !give a user can make per day, default: 5", validate: is_positive_number },
+    RoomSetting { key: "season_top", description: "Number of users that are announced when a season ends with !season end, default: 3", validate: is_positive_number },
+    RoomSetting { key: "list_page_size", description: "Users per page of !list, default: 20", validate: is_positive_number },
+    RoomSetting { key: "ping_changes", description: "on or off, notify users when their score was changed", validate: |value| parse_switch(value).is_some() },
+    RoomSetting { key: "ping_cooldown", description: "on or off, notify users in cooldown notices", validate: |value| parse_switch(value).is_some() },
+    RoomSetting { key: "ping_list", description: "on or off, notify everyone in the !list answer", validate: |value| parse_switch(value).is_some() },
+];
+
+fn is_number(value: &str) -> bool {
+    value.parse::<u32>().is_ok()
+}
+
+fn is_integer(value: &str) -> bool {
+    value.parse::<i32>().is_ok()
+}
+
+fn is_non_negative_number(value: &str) -> bool {
+    value.parse::<i32>().is_ok_and(|value| value >= 0)
+}
+
+fn is_positive_number(value: &str) -> bool {
+    value.parse::<i32>().is_ok_and(|value| value > 0)
+}
+
+fn parse_switch(value: &str) -> Option<bool> {
+    match value.to_lowercase().as_str() {
+        "on" | "true" => Some(true),
+        "off" | "false" => Some(false),
+        _ => None,
+    }
+}
+
+/// Reads an on or off setting, the default is used if it is not set or invalid
+pub fn get_switch_setting(settings: &HashMap<String, String>, key: &str, default: bool) -> bool {
+    settings.get(key).and_then(|value| parse_switch(value)).unwrap_or(default)
+}
+
+pub fn find_room_setting(key: &str) -> Option<&'static RoomSetting> {
+    ROOM_SETTINGS.iter().find(|setting| setting.key == key)
+}
+
+impl RoomSetting {
+    pub fn is_valid(&self, value: &str) -> bool {
+        (self.validate)(value)
+    }
+}
+
+pub fn get_room_config_answer(conn: &Arc<Mutex<Connection>>, room_id: &str) -> HtmlAndTextAnswer {
+    let settings = find_room_settings_in_db(conn, room_id);
+
+    let mut text_body = String::from("Room settings:");
+    let mut html_body = String::from("<h3>Room settings:</h3><br>");
+    for setting in ROOM_SETTINGS {
+        let value = settings.get(setting.key).map_or("default", |value| value.as_str());
+        text_body.push_str(&format!("\n{}: {} ({})", setting.key, value, setting.description));
+        html_body.push_str(&format!("{}: <b>{}</b> ({})<br>", setting.key, value, setting.description));
+    }
+
+    HtmlAndTextAnswer {
+        html: html_body,
+        text: text_body,
+    }
+}
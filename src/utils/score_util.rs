@@ -0,0 +1,194 @@
+use std::collections::HashMap;
+use crate::utils::rank_util::parse_rank_range;
+
+/// Limits of the scores of a room, change_social_credit and transfer_social_credit apply every change of a score
+/// with apply_change so no score leaves the bounds. Only the reset at the end of a season ignores them
+pub struct ScoreBounds {
+    pub min: i32,
+    pub max: i32,
+    pub max_change: Option<i32>, // The largest absolute change of a single reaction
+}
+
+/// The result of a change after the bounds were applied
+pub struct ScoreChange {
+    pub delta: i32, // The change that was actually applied
+    pub new_social_credit: i32,
+    pub clamped: bool,
+}
+
+impl ScoreBounds {
+    pub fn from_settings(settings: &HashMap<String, String>) -> Self {
+        let get_number = |key: &str| settings.get(key).and_then(|value| value.parse::<i32>().ok());
+        let min = get_number("score_min").unwrap_or(i32::MIN);
+        ScoreBounds {
+            min,
+            max: get_number("score_max").unwrap_or(i32::MAX).max(min),
+            max_change: get_number("max_change").filter(|max_change| *max_change > 0),
+        }
+    }
+
+    /// The same bounds without the limit per reaction, for changes that are not made by reactions
+    pub fn without_max_change(&self) -> Self {
+        ScoreBounds {
+            min: self.min,
+            max: self.max,
+            max_change: None,
+        }
+    }
+
+    /// Applies delta to social_credit, the delta is limited to max_change first and the result to min and max.
+    /// A score that is already outside of the bounds, because they were changed, can only move towards them
+    pub fn apply_change(&self, social_credit: i32, delta: i32) -> ScoreChange {
+        let limited_delta = match self.max_change {
+            Some(max_change) => delta.clamp(-max_change, max_change),
+            None => delta,
+        };
+        let mut new_social_credit = social_credit.saturating_add(limited_delta);
+        if limited_delta > 0 {
+            new_social_credit = new_social_credit.min(self.max.max(social_credit));
+        }
+        else if limited_delta < 0 {
+            new_social_credit = new_social_credit.max(self.min.min(social_credit));
+        }
+
+        let applied_delta = (new_social_credit as i64 - social_credit as i64) as i32;
+        ScoreChange {
+            delta: applied_delta,
+            new_social_credit,
+            clamped: applied_delta != delta,
+        }
+    }
+}
+
+/// Multipliers for the changes of reactions depending on the score of the reacting user, the first matching range is used
+/// and users outside of all ranges have a multiplier of 1. Written as range=multiplier pairs, e.g. <0=0.5,>=1000=1.5
+pub struct ReactionWeights {
+    tiers: Vec<WeightTier>,
+}
+
+/// Min inclusive, max exclusive and the multiplier
+type WeightTier = (Option<i32>, Option<i32>, f64);
+
+impl ReactionWeights {
+    pub fn parse(text: &str) -> Option<Self> {
+        let tiers: Option<Vec<WeightTier>> = text.split(',')
+            .map(|tier| {
+                let (range, multiplier) = tier.trim().rsplit_once('=')?;
+                let (min, max) = parse_rank_range(range)?;
+                let multiplier = multiplier.parse::<f64>().ok().filter(|multiplier| multiplier.is_finite() && *multiplier >= 0.0)?;
+                Some((min, max, multiplier))
+            })
+            .collect();
+        Some(ReactionWeights { tiers: tiers? })
+    }
+
+    /// Weighting is off if reaction_weights is not set
+    pub fn from_settings(settings: &HashMap<String, String>) -> Option<Self> {
+        settings.get("reaction_weights").and_then(|value| ReactionWeights::parse(value))
+    }
+
+    pub fn get_multiplier(&self, social_credit: i32) -> f64 {
+        self.tiers.iter()
+            .find(|(min, max, _)| !matches!(min, Some(min) if social_credit < *min) && !matches!(max, Some(max) if social_credit >= *max))
+            .map_or(1.0, |(_, _, multiplier)| *multiplier)
+    }
+}
+
+/// Scales the change of a reaction, rounded and saturated to the i32 range
+pub fn scale_delta(delta: i32, multiplier: f64) -> i32 {
+    (delta as f64 * multiplier).round() as i32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bounds(min: i32, max: i32, max_change: Option<i32>) -> ScoreBounds {
+        ScoreBounds { min, max, max_change }
+    }
+
+    #[test]
+    fn apply_change_within_bounds() {
+        let change = bounds(-100, 100, None).apply_change(0, 50);
+        assert_eq!((change.delta, change.new_social_credit, change.clamped), (50, 50, false));
+    }
+
+    #[test]
+    fn apply_change_clamps_to_the_bounds() {
+        let change = bounds(-100, 100, None).apply_change(90, 20);
+        assert_eq!((change.delta, change.new_social_credit, change.clamped), (10, 100, true));
+        let change = bounds(-100, 100, None).apply_change(-90, -20);
+        assert_eq!((change.delta, change.new_social_credit, change.clamped), (-10, -100, true));
+        let change = bounds(-100, 100, None).apply_change(100, 0);
+        assert_eq!((change.delta, change.new_social_credit, change.clamped), (0, 100, false));
+    }
+
+    #[test]
+    fn apply_change_limits_the_change() {
+        let change = bounds(i32::MIN, i32::MAX, Some(10)).apply_change(0, -25);
+        assert_eq!((change.delta, change.new_social_credit, change.clamped), (-10, -10, true));
+        let change = bounds(i32::MIN, i32::MAX, Some(10)).apply_change(0, 10);
+        assert_eq!((change.delta, change.new_social_credit, change.clamped), (10, 10, false));
+    }
+
+    #[test]
+    fn apply_change_outside_of_the_bounds() {
+        // A score above the max does not grow further and is not pulled down by a positive change
+        let change = bounds(0, 100, None).apply_change(150, 10);
+        assert_eq!((change.delta, change.new_social_credit, change.clamped), (0, 150, true));
+        // Changes towards the bounds are applied in full
+        let change = bounds(0, 100, None).apply_change(150, -10);
+        assert_eq!((change.delta, change.new_social_credit, change.clamped), (-10, 140, false));
+        let change = bounds(0, 100, None).apply_change(-50, 10);
+        assert_eq!((change.delta, change.new_social_credit, change.clamped), (10, -40, false));
+    }
+
+    #[test]
+    fn apply_change_saturates() {
+        let change = bounds(i32::MIN, i32::MAX, None).apply_change(i32::MAX - 1, 5);
+        assert_eq!((change.delta, change.new_social_credit, change.clamped), (1, i32::MAX, true));
+        let change = bounds(i32::MIN, i32::MAX, None).apply_change(i32::MIN, i32::MIN);
+        assert_eq!((change.delta, change.new_social_credit, change.clamped), (0, i32::MIN, true));
+    }
+
+    #[test]
+    fn without_max_change() {
+        let change = bounds(-100, 100, Some(10)).without_max_change().apply_change(0, 50);
+        assert_eq!((change.delta, change.clamped), (50, false));
+    }
+
+    #[test]
+    fn reaction_weights_parse() {
+        let weights = ReactionWeights::parse("<0=0.5, >=1000=1.5, 0..1000=1").unwrap();
+        assert_eq!(weights.get_multiplier(-1), 0.5);
+        assert_eq!(weights.get_multiplier(0), 1.0);
+        assert_eq!(weights.get_multiplier(999), 1.0);
+        assert_eq!(weights.get_multiplier(1000), 1.5);
+    }
+
+    #[test]
+    fn reaction_weights_first_matching_range_is_used() {
+        let weights = ReactionWeights::parse(">=0=2,>=10=3").unwrap();
+        assert_eq!(weights.get_multiplier(20), 2.0);
+        assert_eq!(weights.get_multiplier(-5), 1.0);
+    }
+
+    #[test]
+    fn reaction_weights_parse_invalid() {
+        assert!(ReactionWeights::parse("").is_none());
+        assert!(ReactionWeights::parse(">=0").is_none());
+        assert!(ReactionWeights::parse(">=0=-1").is_none());
+        assert!(ReactionWeights::parse(">=0=NaN").is_none());
+        assert!(ReactionWeights::parse(">=0=inf").is_none());
+        assert!(ReactionWeights::parse("10..0=2").is_none());
+        assert!(ReactionWeights::parse(">=0=2,foo=1").is_none());
+    }
+
+    #[test]
+    fn scale_delta_rounds_and_saturates() {
+        assert_eq!(scale_delta(5, 0.5), 3);
+        assert_eq!(scale_delta(-5, 0.5), -3);
+        assert_eq!(scale_delta(10, 0.0), 0);
+        assert_eq!(scale_delta(i32::MAX, 2.0), i32::MAX);
+    }
+}
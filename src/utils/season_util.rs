@@ -0,0 +1,62 @@
+use std::sync::{Arc, Mutex};
+use matrix_sdk::room::Joined;
+use matrix_sdk::ruma::UserId;
+use rusqlite::Connection;
+use crate::data::season::{find_all_seasons_for_room_in_db, find_season_standings_in_db, SeasonStanding};
+use crate::data::user::HtmlAndTextAnswer;
+use crate::utils::mention_util::{get_display_name, MentionMessage};
+use crate::utils::user_util::disambiguate_names;
+use crate::web::pages::{escape_html, format_time};
+
+pub fn get_season_list_answer(conn: &Arc<Mutex<Connection>>, room_id: &str) -> HtmlAndTextAnswer {
+    let seasons = find_all_seasons_for_room_in_db(conn, room_id).unwrap_or_default();
+    if seasons.is_empty() {
+        return HtmlAndTextAnswer {
+            html: String::from("No season has ended yet"),
+            text: String::from("No season has ended yet"),
+        };
+    }
+
+    let mut text_body = String::from("Seasons:");
+    let mut html_body = String::from("<h3>Seasons:</h3>");
+    for season in seasons {
+        let winner = find_season_standings_in_db(conn, season.id).unwrap_or_default().into_iter().next();
+        let line = match winner {
+            Some(winner) => format!("Season {}, ended {}, winner: {} with {}", season.number, format_time(season.ended_at), winner.mxid, winner.social_credit),
+            None => format!("Season {}, ended {}, no scores", season.number, format_time(season.ended_at)),
+        };
+        text_body.push_str(&format!("\n{}", line));
+        html_body.push_str(&format!("<br>{}", escape_html(&line)));
+    }
+
+    HtmlAndTextAnswer {
+        html: html_body,
+        text: text_body,
+    }
+}
+
+/// The standings with the display names of the users in the room, only the first limit users if limit is set
+pub async fn get_season_standings_message(room: &Joined, heading: &str, standings: &[SeasonStanding], limit: Option<usize>) -> MentionMessage {
+    let standings = &standings[..limit.unwrap_or(standings.len()).min(standings.len())];
+    let mut entries = Vec::new();
+    let mut names = Vec::new();
+    for standing in standings {
+        if let Ok(user_id) = UserId::parse(standing.mxid.as_str()) {
+            names.push((user_id.to_string(), get_display_name(room, &user_id).await));
+            entries.push((user_id, standing.social_credit));
+        }
+    }
+    disambiguate_names(&mut names);
+
+    let mut message = MentionMessage::default();
+    message.push(heading, &format!("<h3>{}</h3>", escape_html(heading)));
+    if entries.is_empty() {
+        message.push("\nNo scores", "<br>No scores");
+    }
+    for (position, ((user_id, social_credit), (_, name))) in entries.iter().zip(names).enumerate() {
+        message.push(&format!("\n{}. ", position + 1), &format!("<br>{}. ", position + 1));
+        message.push_mention(user_id, &name);
+        message.push(&format!(": {}", social_credit), &format!(": <b>{}</b>", social_credit));
+    }
+    message
+}
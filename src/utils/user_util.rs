@@ -1,69 +1,78 @@
 use std::sync::{Arc, Mutex};
 use matrix_sdk::room::{Joined};
-use regex::Regex;
+use matrix_sdk::ruma::{OwnedUserId, ServerName, UserId};
 use rusqlite::Connection;
-use crate::data::user::{find_all_users_with_room_data_in_db, find_user_in_db, insert_user, update_user, User, HtmlAndTextAnswer, UserType};
-use crate::data::user_room_data::{find_user_room_data_by_user_id_and_room_id, insert_user_room_data, UserRoomData};
+use crate::data::rank::find_all_ranks_for_room_in_db;
+use crate::data::user::{find_user_in_db, insert_user, update_user, User, UserType};
+use crate::data::user_room_data::{count_users_in_room_in_db, find_placement_in_db, find_standings_in_db, find_user_room_data_by_user_id_and_room_id, insert_user_room_data, Standing, UserRoomData};
+use crate::utils::mention_util::{get_display_name, MentionMessage};
+use crate::utils::rank_util::get_rank;
+use crate::web::pages::escape_html;
 
 pub fn compare_user(user1: &User, user2: &User) -> bool {
-    user1.name == user2.name && user1.url == user2.url
+    user1.mxid == user2.mxid
 }
 
-pub fn extract_userdata_from_string(body: &str) -> Option<(String, String)> {
-    let re = Regex::new(r#"@(?P<username>[^:]+):(?P<domain>[^">]+)"#).unwrap();
-
-    if let Some(captures) = re.captures(body) {
-        let username = captures.name("username").unwrap().as_str().to_string();
-        let domain = captures.name("domain").unwrap().as_str().to_string();
-        return Some((username, domain));
+/// Appends the matrix id to every name that is used by more than one user, names are (matrix id, name)
+pub fn disambiguate_names(names: &mut [(String, String)]) {
+    let duplicates: Vec<String> = names.iter()
+        .filter(|(mxid, name)| names.iter().any(|(other_mxid, other)| other == name && other_mxid != mxid))
+        .map(|(_, name)| name.clone())
+        .collect();
+    for (mxid, name) in names.iter_mut() {
+        if duplicates.contains(name) {
+            *name = format!("{} ({})", name, mxid);
+        }
     }
-    None
 }
 
-pub fn setup_user(conn: &Arc<Mutex<Connection>>, room: Option<Joined>, user_tag: &String, user_type: UserType, initial_social_credit: i32) -> Option<User> {
-    if let Some((username, domain)) = extract_userdata_from_string(user_tag) {
-        let user_opt = find_user_in_db(conn, &username, &domain);
-        let mut mut_user_opt = user_opt.clone().take();
-        if let Some(ref mut actual_user) = mut_user_opt {
-            setup_user_room_data_for_room(conn, room, actual_user, initial_social_credit);
-            return Some(actual_user.clone());
+/// Finds the user by the matrix id or creates it, user_tag has to be a valid matrix id like @alice:matrix.org
+pub fn setup_user(conn: &Arc<Mutex<Connection>>, room_id: Option<&str>, user_tag: &String, user_type: UserType, initial_social_credit: i32) -> Option<User> {
+    let user_id = match UserId::parse(user_tag.as_str()) {
+        Ok(user_id) => user_id,
+        Err(e) => {
+            println!("Invalid user id {}: {}", user_tag, e); // debug level
+            return None;
         }
+    };
 
-        println!("User {} not found in db, creating new one", user_tag); // debug level
+    if let Some(mut user) = find_user_in_db(conn, user_id.as_str()) {
+        setup_user_room_data_for_room(conn, room_id, &mut user, initial_social_credit);
+        return Some(user);
+    }
 
-        let user = User {
-            id: -1,
-            name: username.clone(),
-            url: domain.clone(),
-            user_type,
-            room_data: None,
-        };
+    println!("User {} not found in db, creating new one", user_id); // debug level
 
-        if insert_user(conn, &user).is_ok() {
-            let user_opt = find_user_in_db(conn, &username, &domain);
-            if user_opt.is_none() {
-                println!("Failed to find user in db after inserting");
-                return None;
-            }
-            let mut mut_user = user_opt.unwrap();
-            setup_user_room_data_for_room(conn, room, &mut mut_user, initial_social_credit);
-            return Some(mut_user.clone());
+    let user = User {
+        id: -1,
+        name: user_id.localpart().to_string(),
+        url: user_id.server_name().to_string(),
+        mxid: user_id.to_string(),
+        user_type,
+        room_data: None,
+    };
+
+    if insert_user(conn, &user).is_ok() {
+        let user_opt = find_user_in_db(conn, user_id.as_str());
+        if user_opt.is_none() {
+            println!("Failed to find user in db after inserting");
+            return None;
         }
+        let mut mut_user = user_opt.unwrap();
+        setup_user_room_data_for_room(conn, room_id, &mut mut_user, initial_social_credit);
+        return Some(mut_user);
     }
     None
 }
 
-fn setup_user_room_data_for_room(conn: &Arc<Mutex<Connection>>, room: Option<Joined>, user: &mut User, initial_social_credit: i32) {
-    if room.is_some() {
-        let room = room.unwrap();
-        let room_data = find_user_room_data_by_user_id_and_room_id(conn, user.id, &room.room_id().to_string());
-        if room_data.is_ok() {
-            let room_data = room_data.unwrap();
+fn setup_user_room_data_for_room(conn: &Arc<Mutex<Connection>>, room_id: Option<&str>, user: &mut User, initial_social_credit: i32) {
+    if let Some(room_id) = room_id {
+        let room_id = room_id.to_string();
+        if let Ok(room_data) = find_user_room_data_by_user_id_and_room_id(conn, user.id, &room_id) {
             user.room_data = Some(room_data);
             return;
         }
 
-        let room_id = room.room_id().to_string();
         println!("Room data for user {} and room {} not found in db, creating", user.name, room_id); // debug level
 
         let room_data = UserRoomData {
@@ -71,79 +80,137 @@ fn setup_user_room_data_for_room(conn: &Arc<Mutex<Connection>>, room: Option<Joi
             user_id: user.id,
             room_id,
             social_credit: initial_social_credit,
-            reactions: Vec::new(),
         };
 
         if insert_user_room_data(conn, &room_data).is_err() {
             println!("Failed to insert room data for user {}", user.name);
+            user.room_data = Some(room_data);
+            return;
         }
 
-        user.room_data = Some(room_data);
+        // Read it back so the room data has its real id
+        user.room_data = Some(find_user_room_data_by_user_id_and_room_id(conn, user.id, &room_data.room_id).unwrap_or(room_data));
     }
 }
 
-pub fn initial_admin_user_setup(conn: &Arc<Mutex<Connection>>, username: &String, homeserver_url_relative: &str) {
-    let admin_user = find_user_in_db(&conn, &username, &homeserver_url_relative.to_string());
-    if admin_user.is_some() {
-        let mut admin_user = admin_user.unwrap();
-        if !matches!(admin_user.user_type, UserType::Admin) {
-            admin_user.user_type = UserType::Admin;
-            update_user(&conn, &admin_user).expect("Failed to update admin user");
-        }
+/// Makes ADMIN_USERNAME an admin, it is either a full matrix id or a username on the server of the bot
+pub fn initial_admin_user_setup(conn: &Arc<Mutex<Connection>>, admin_username: &str, server_name: &ServerName) {
+    let admin_user_id = if admin_username.starts_with('@') {
+        admin_username.to_string()
     }
-    else if admin_user.is_none() {
-        setup_user(&conn, None, &format!("@{}:{}", username, homeserver_url_relative), UserType::Admin, -1).expect("Failed to construct or register admin user");
+    else {
+        format!("@{}:{}", admin_username, server_name)
+    };
+
+    let mut admin_user = setup_user(conn, None, &admin_user_id, UserType::Admin, -1).expect("Failed to construct or register admin user");
+    if !matches!(admin_user.user_type, UserType::Admin) {
+        admin_user.user_type = UserType::Admin;
+        update_user(conn, &admin_user).expect("Failed to update admin user");
     }
 }
 
-pub fn get_user_list_answer(conn: &Arc<Mutex<Connection>>, room: &Joined) -> HtmlAndTextAnswer {
-    let users_opt = find_all_users_with_room_data_in_db(&conn, &room.room_id().to_string());
-    let empty_answer = HtmlAndTextAnswer {
-        html: String::from("No scores"),
-        text: String::from("No Scores"),
-    };
+/// One page of the scores of the room with pills, pages start at 1. Also returns the ids of the listed users so they can be pinged
+pub async fn get_user_list_answer(conn: &Arc<Mutex<Connection>>, room: &Joined, bot_user_id: &UserId, page: usize, page_size: usize) -> (MentionMessage, Vec<OwnedUserId>) {
+    let room_id = room.room_id().as_str();
+    let mut message = MentionMessage::default();
+    let total = count_users_in_room_in_db(conn, room_id, bot_user_id.as_str()).unwrap_or(0);
+    if total == 0 {
+        message.push("No scores", "No scores");
+        return (message, Vec::new());
+    }
 
-    if users_opt.is_none() {
-        return empty_answer;
+    let page_count = (total + page_size - 1) / page_size;
+    if page > page_count {
+        let text = format!("There are only {} pages", page_count);
+        message.push(&text, &text);
+        return (message, Vec::new());
     }
 
-    let mut text_body = String::from("Social Credit Scores: ");
-    let mut html_body = String::from("<h3>Social Credit Scores:</h3><br>");
+    let standings = find_standings_in_db(conn, room_id, bot_user_id.as_str(), false, page_size, (page - 1) * page_size).unwrap_or_default();
+    let heading = if page_count > 1 { format!("Social Credit Scores (page {} of {}):", page, page_count) } else { String::from("Social Credit Scores:") };
+    message.push(&heading, &format!("<h3>{}</h3>", heading));
+    let user_ids = push_standings(conn, &mut message, room, &standings).await;
+    if page < page_count {
+        let text = format!("Use !list {} for the next page", page + 1);
+        message.push(&format!("\n{}", text), &format!("<br>{}", text));
+    }
 
-    let mut users = users_opt.unwrap();
+    (message, user_ids)
+}
 
-    if users.len() == 0 {
-        return empty_answer;
+/// The highest scores of the room or the lowest ones if lowest_first is set
+pub async fn get_leaderboard_answer(conn: &Arc<Mutex<Connection>>, room: &Joined, bot_user_id: &UserId, lowest_first: bool, count: usize) -> MentionMessage {
+    let standings = find_standings_in_db(conn, room.room_id().as_str(), bot_user_id.as_str(), lowest_first, count, 0).unwrap_or_default();
+    let mut message = MentionMessage::default();
+    if standings.is_empty() {
+        message.push("No scores", "No scores");
+        return message;
     }
 
-    // Sort users by social credit
-    users.sort_by(|a, b| {
-        let a_credit = a.room_data.as_ref().map_or(0, |sc| sc.social_credit);
-        let b_credit = b.room_data.as_ref().map_or(0, |sc| sc.social_credit);
-        b_credit.cmp(&a_credit)
-    });
-
-    for user in users {
-        let room_data_opt = user.room_data;
-        if room_data_opt.is_none() {
-            continue;
-        }
-        let room_data = room_data_opt.unwrap();
-        text_body.push_str(&format!("{}: {},", user.name, room_data.social_credit));
-        html_body.push_str(&format!("{}: <b>{}</b><br>", user.name, room_data.social_credit));
-    }
+    let heading = format!("{} {} Social Credit Scores:", if lowest_first { "Bottom" } else { "Top" }, standings.len());
+    message.push(&heading, &format!("<h3>{}</h3>", heading));
+    push_standings(conn, &mut message, room, &standings).await;
+    message
+}
 
-    // Remove the last comma
-    if text_body.len() >= 1 {
-        text_body.remove(text_body.len() - 1);
+/// Appends a line with the position, pill, rank and score for every standing and returns the ids of the listed users
+async fn push_standings(conn: &Arc<Mutex<Connection>>, message: &mut MentionMessage, room: &Joined, standings: &[Standing]) -> Vec<OwnedUserId> {
+    let mut entries = Vec::new();
+    let mut names = Vec::new();
+    for standing in standings {
+        if let Ok(user_id) = UserId::parse(standing.mxid.as_str()) {
+            names.push((user_id.to_string(), get_display_name(room, &user_id).await));
+            entries.push((user_id, standing));
+        }
     }
-    // Remove the last <br>
-    if html_body.len() >= 4 {
-        html_body.truncate(html_body.len() - 4);
+    disambiguate_names(&mut names);
+
+    let ranks = find_all_ranks_for_room_in_db(conn, room.room_id().as_str()).unwrap_or_default();
+    for ((user_id, standing), (_, name)) in entries.iter().zip(names) {
+        message.push(&format!("\n{}. ", standing.position), &format!("<br>{}. ", standing.position));
+        message.push_mention(user_id, &name);
+        if let Some(rank) = get_rank(&ranks, standing.social_credit) {
+            message.push(&format!(" [{}]", rank.title), &format!(" [{}]", escape_html(&rank.title)));
+        }
+        message.push(&format!(": {}", standing.social_credit), &format!(": <b>{}</b>", standing.social_credit));
     }
 
-    HtmlAndTextAnswer {
-        html: html_body.to_string(),
-        text: text_body.to_string(),
+    entries.into_iter().map(|(user_id, _)| user_id).collect()
+}
+
+/// The position of the user in the room, the share of the room with a lower score and the gap to the next higher score
+pub async fn get_placement_answer(conn: &Arc<Mutex<Connection>>, room: &Joined, bot_user_id: &UserId, user_id: &UserId) -> MentionMessage {
+    let room_id = room.room_id().to_string();
+    let mut message = MentionMessage::default();
+    message.push_mention(user_id, &get_display_name(room, user_id).await);
+
+    let room_data = find_user_in_db(conn, user_id.as_str())
+        .filter(|_| user_id != bot_user_id)
+        .and_then(|user| find_user_room_data_by_user_id_and_room_id(conn, user.id, &room_id).ok());
+    let placement = room_data.as_ref()
+        .and_then(|room_data| find_placement_in_db(conn, &room_id, bot_user_id.as_str(), room_data.social_credit));
+    let (social_credit, placement) = match (room_data, placement) {
+        (Some(room_data), Some(placement)) => (room_data.social_credit, placement),
+        _ => {
+            message.push(" has no score in this room", " has no score in this room");
+            return message;
+        }
+    };
+
+    let lower = placement.total.saturating_sub(placement.higher + placement.same);
+    let percentile = if placement.total > 1 { lower * 100 / (placement.total - 1) } else { 100 };
+    let mut text = format!(
+        " is #{}{} of {} with {} social credit, ahead of {}% of the room",
+        placement.higher + 1, if placement.same > 1 { " (shared)" } else { "" }, placement.total, social_credit, percentile,
+    );
+    let ranks = find_all_ranks_for_room_in_db(conn, &room_id).unwrap_or_default();
+    if let Some(rank) = get_rank(&ranks, social_credit) {
+        text.push_str(&format!(", rank: {}", rank.title));
+    }
+    match placement.next_social_credit {
+        Some(next_social_credit) => text.push_str(&format!(". {} more to catch up with the next higher score", next_social_credit as i64 - social_credit as i64)),
+        None => text.push_str(". Nobody is ahead"),
     }
-}
\ No newline at end of file
+    message.push(&text, &escape_html(&text));
+    message
+}
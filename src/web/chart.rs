@@ -0,0 +1,69 @@
+use std::time::{Duration, SystemTime};
+use crate::data::transaction::Transaction;
+use crate::web::pages::format_time;
+
+const WIDTH: f64 = 800.0;
+const HEIGHT: f64 = 300.0;
+const PADDING: f64 = 50.0;
+
+/// Renders the score of a user over time as an inline svg step chart,
+/// transactions need to be sorted by time, oldest first
+pub fn render_score_chart(transactions: &[Transaction], current_social_credit: i32) -> String {
+    if transactions.is_empty() {
+        return String::new();
+    }
+
+    // The score before the first recorded change, followed by the score after every change
+    let mut points: Vec<(SystemTime, i32)> = Vec::with_capacity(transactions.len() + 2);
+    points.push((transactions[0].time, transactions[0].social_credit - transactions[0].delta));
+    for transaction in transactions {
+        points.push((transaction.time, transaction.social_credit));
+    }
+    points.push((SystemTime::now().max(transactions[transactions.len() - 1].time), current_social_credit));
+
+    let start = to_secs(points[0].0);
+    let end = to_secs(points[points.len() - 1].0).max(start + 1.0);
+    let min = points.iter().map(|point| point.1).min().unwrap_or(0) as f64;
+    let max = points.iter().map(|point| point.1).max().unwrap_or(0) as f64;
+    let (min, max) = if min == max { (min - 1.0, max + 1.0) } else { (min, max) };
+
+    let x = |time: SystemTime| PADDING + (to_secs(time) - start) / (end - start) * (WIDTH - 2.0 * PADDING);
+    let y = |value: f64| HEIGHT - PADDING - (value - min) / (max - min) * (HEIGHT - 2.0 * PADDING);
+
+    let mut path = String::new();
+    let mut previous_y = y(points[0].1 as f64);
+    for (index, (time, value)) in points.iter().enumerate() {
+        let point_x = x(*time);
+        let point_y = y(*value as f64);
+        if index == 0 {
+            path.push_str(&format!("M{:.1},{:.1}", point_x, point_y));
+        }
+        else {
+            path.push_str(&format!(" L{:.1},{:.1} L{:.1},{:.1}", point_x, previous_y, point_x, point_y));
+        }
+        previous_y = point_y;
+    }
+
+    let mut svg = format!("<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{w}\" height=\"{h}\" viewBox=\"0 0 {w} {h}\">", w = WIDTH, h = HEIGHT);
+    svg.push_str(&format!(
+        "<line x1=\"{p}\" y1=\"{b}\" x2=\"{r}\" y2=\"{b}\" stroke=\"#999\"/><line x1=\"{p}\" y1=\"{p}\" x2=\"{p}\" y2=\"{b}\" stroke=\"#999\"/>",
+        p = PADDING, b = HEIGHT - PADDING, r = WIDTH - PADDING
+    ));
+    if min < 0.0 && max > 0.0 {
+        svg.push_str(&format!("<line x1=\"{}\" y1=\"{y:.1}\" x2=\"{}\" y2=\"{y:.1}\" stroke=\"#ccc\" stroke-dasharray=\"4\"/>", PADDING, WIDTH - PADDING, y = y(0.0)));
+    }
+    svg.push_str(&format!("<path d=\"{}\" fill=\"none\" stroke=\"#0b5cad\" stroke-width=\"2\"/>", path));
+    svg.push_str(&format!("<text x=\"{}\" y=\"{:.1}\" font-size=\"12\" text-anchor=\"end\">{}</text>", PADDING - 5.0, y(max) + 4.0, max));
+    svg.push_str(&format!("<text x=\"{}\" y=\"{:.1}\" font-size=\"12\" text-anchor=\"end\">{}</text>", PADDING - 5.0, y(min) + 4.0, min));
+    svg.push_str(&format!("<text x=\"{}\" y=\"{}\" font-size=\"12\">{}</text>", PADDING, HEIGHT - PADDING + 20.0, format_time(points[0].0)));
+    svg.push_str(&format!(
+        "<text x=\"{}\" y=\"{}\" font-size=\"12\" text-anchor=\"end\">{}</text>",
+        WIDTH - PADDING, HEIGHT - PADDING + 20.0, format_time(points[points.len() - 1].0)
+    ));
+    svg.push_str("</svg>");
+    svg
+}
+
+fn to_secs(time: SystemTime) -> f64 {
+    time.duration_since(SystemTime::UNIX_EPOCH).unwrap_or(Duration::from_secs(0)).as_secs_f64()
+}
@@ -0,0 +1,83 @@
+pub mod pages;
+pub mod chart;
+
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use hyper::header::CONTENT_TYPE;
+use hyper::service::{make_service_fn, service_fn};
+use percent_encoding::percent_decode_str;
+use rusqlite::Connection;
+
+/// Serves the read only dashboard until the process exits, everything is rendered on the server
+/// and the pages do not load any external resources so it also works offline
+/// The bot itself is not listed, it is identified by bot_user_id
+pub async fn run_dashboard(conn: Arc<Mutex<Connection>>, address: SocketAddr, bot_user_id: String) {
+    let make_service = make_service_fn(move |_| {
+        let conn = conn.clone();
+        let bot_user_id = bot_user_id.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| {
+                let conn = conn.clone();
+                let bot_user_id = bot_user_id.clone();
+                async move { Ok::<_, Infallible>(handle_request(&conn, &bot_user_id, request)) }
+            }))
+        }
+    });
+
+    let server = match Server::try_bind(&address) {
+        Ok(builder) => builder.serve(make_service),
+        Err(e) => {
+            println!("Unable to start the dashboard on {}: {}", address, e); // error level
+            return;
+        }
+    };
+
+    println!("Dashboard available at http://{}", address);
+    if let Err(e) = server.await {
+        println!("Dashboard error: {}", e); // error level
+    }
+}
+
+fn handle_request(conn: &Arc<Mutex<Connection>>, bot_user_id: &str, request: Request<Body>) -> Response<Body> {
+    if request.method() != Method::GET {
+        return html_response(StatusCode::METHOD_NOT_ALLOWED, pages::error_page("Method not allowed"));
+    }
+
+    let segments: Vec<String> = request.uri().path()
+        .split('/')
+        .filter(|segment| !segment.is_empty())
+        .map(|segment| percent_decode_str(segment).decode_utf8_lossy().to_string())
+        .collect();
+
+    let page = match segments.iter().map(|s| s.as_str()).collect::<Vec<&str>>().as_slice() {
+        [] => Some(pages::index_page(conn, bot_user_id)),
+        ["room", room_id] => pages::room_page(conn, room_id, bot_user_id, get_page(request.uri().query())),
+        ["room", room_id, "user", user_id] => user_id.parse::<i32>().ok()
+            .and_then(|user_id| pages::user_page(conn, room_id, user_id)),
+        _ => None,
+    };
+
+    match page {
+        Some(html) => html_response(StatusCode::OK, html),
+        None => html_response(StatusCode::NOT_FOUND, pages::error_page("Not found")),
+    }
+}
+
+/// The page of a paginated list from the query, like ?page=2, the first page if it is missing or invalid
+fn get_page(query: Option<&str>) -> usize {
+    query.unwrap_or("")
+        .split('&')
+        .find_map(|pair| pair.strip_prefix("page="))
+        .and_then(|page| page.parse::<usize>().ok())
+        .filter(|page| *page > 0)
+        .unwrap_or(1)
+}
+
+fn html_response(status: StatusCode, html: String) -> Response<Body> {
+    let mut response = Response::new(Body::from(html));
+    *response.status_mut() = status;
+    response.headers_mut().insert(CONTENT_TYPE, "text/html; charset=utf-8".parse().unwrap());
+    response
+}
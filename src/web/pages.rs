@@ -0,0 +1,204 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+use chrono::{DateTime, Utc};
+use matrix_sdk::ruma::UserId;
+use percent_encoding::{NON_ALPHANUMERIC, utf8_percent_encode};
+use rusqlite::Connection;
+use crate::data::emoji::find_all_emoji_for_room_in_db;
+use crate::data::transaction::find_transactions_for_user_room_data;
+use crate::data::user::find_user_by_id_in_db;
+use crate::data::user_room_data::{count_users_in_room_in_db, find_all_room_ids_in_db, find_standings_in_db, find_user_room_data_by_user_id_and_room_id};
+use crate::utils::user_util::disambiguate_names;
+use crate::web::chart::render_score_chart;
+
+const LEADERBOARD_PAGE_SIZE: usize = 50;
+
+const STYLE: &str = "body{font-family:sans-serif;max-width:900px;margin:2em auto;padding:0 1em;color:#222}\
+    table{border-collapse:collapse;margin-bottom:2em}td,th{padding:.3em .8em;border-bottom:1px solid #ddd;text-align:left}\
+    td.num,th.num{text-align:right}a{color:#0b5cad;text-decoration:none}small{color:#777}";
+
+pub fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+pub fn format_time(time: SystemTime) -> String {
+    DateTime::<Utc>::from(time).format("%Y-%m-%d %H:%M").to_string()
+}
+
+fn room_link(room_id: &str) -> String {
+    format!("/room/{}", utf8_percent_encode(room_id, NON_ALPHANUMERIC))
+}
+
+fn layout(title: &str, body: &str) -> String {
+    format!(
+        "<!DOCTYPE html><html><head><meta charset=\"utf-8\"><title>{} - Social Credit System</title><style>{}</style></head><body>{}</body></html>",
+        escape_html(title), STYLE, body
+    )
+}
+
+pub fn error_page(message: &str) -> String {
+    layout(message, &format!("<h1>{}</h1><p><a href=\"/\">Back to all rooms</a></p>", escape_html(message)))
+}
+
+pub fn index_page(conn: &Arc<Mutex<Connection>>, bot_user_id: &str) -> String {
+    let room_ids = match find_all_room_ids_in_db(conn) {
+        Ok(room_ids) => room_ids,
+        Err(e) => {
+            println!("Database error: {}", e);
+            Vec::new()
+        }
+    };
+
+    let mut body = String::from("<h1>Social Credit System</h1>");
+    if room_ids.is_empty() {
+        body.push_str("<p>No rooms yet</p>");
+        return layout("Rooms", &body);
+    }
+
+    body.push_str("<table><tr><th>Room</th><th class=\"num\">Users</th></tr>");
+    for room_id in room_ids {
+        let user_count = count_users_in_room_in_db(conn, &room_id, bot_user_id).unwrap_or(0);
+        body.push_str(&format!(
+            "<tr><td><a href=\"{}\">{}</a></td><td class=\"num\">{}</td></tr>",
+            room_link(&room_id), escape_html(&room_id), user_count
+        ));
+    }
+    body.push_str("</table>");
+
+    layout("Rooms", &body)
+}
+
+/// The leaderboard is paginated with LEADERBOARD_PAGE_SIZE users per page, page starts at 1
+pub fn room_page(conn: &Arc<Mutex<Connection>>, room_id: &str, bot_user_id: &str, page: usize) -> Option<String> {
+    let room_id = room_id.to_string();
+    let total = count_users_in_room_in_db(conn, &room_id, bot_user_id)?;
+    let mut emojis = find_all_emoji_for_room_in_db(conn, &room_id)?;
+    let page_count = ((total + LEADERBOARD_PAGE_SIZE - 1) / LEADERBOARD_PAGE_SIZE).max(1);
+    if (total == 0 && emojis.is_empty()) || page > page_count {
+        return None;
+    }
+
+    let standings = find_standings_in_db(conn, &room_id, bot_user_id, false, LEADERBOARD_PAGE_SIZE, (page - 1) * LEADERBOARD_PAGE_SIZE)?;
+    emojis.sort_by_key(|emoji| -emoji.social_credit);
+
+    let mut body = format!("<p><a href=\"/\">All rooms</a></p><h1>{}</h1><h2>Leaderboard</h2>", escape_html(&room_id));
+    if standings.is_empty() {
+        body.push_str("<p>No scores</p>");
+    }
+    else {
+        let mut names: Vec<(String, String)> = standings.iter()
+            .map(|standing| (standing.mxid.clone(), UserId::parse(standing.mxid.as_str()).map_or(standing.mxid.clone(), |user_id| user_id.localpart().to_string())))
+            .collect();
+        disambiguate_names(&mut names);
+
+        body.push_str("<table><tr><th class=\"num\">#</th><th>User</th><th class=\"num\">Social Credit</th></tr>");
+        for (standing, (_, name)) in standings.iter().zip(names) {
+            body.push_str(&format!(
+                "<tr><td class=\"num\">{}</td><td><a href=\"{}/user/{}\" title=\"{}\">{}</a></td><td class=\"num\">{}</td></tr>",
+                standing.position, room_link(&room_id), standing.user_id, escape_html(&standing.mxid), escape_html(&name), standing.social_credit
+            ));
+        }
+        body.push_str("</table>");
+    }
+    if page_count > 1 {
+        body.push_str(&format!("<p>Page {} of {}", page, page_count));
+        if page > 1 {
+            body.push_str(&format!(" · <a href=\"{}?page={}\">Previous</a>", room_link(&room_id), page - 1));
+        }
+        if page < page_count {
+            body.push_str(&format!(" · <a href=\"{}?page={}\">Next</a>", room_link(&room_id), page + 1));
+        }
+        body.push_str("</p>");
+    }
+
+    body.push_str("<h2>Registered Emojis</h2>");
+    if emojis.is_empty() {
+        body.push_str("<p>No emojis</p>");
+    }
+    else {
+        body.push_str("<table><tr><th>Emoji</th><th class=\"num\">Social Credit</th></tr>");
+        for emoji in emojis {
+            body.push_str(&format!("<tr><td>{}</td><td class=\"num\">{}</td></tr>", escape_html(&emoji.emoji), emoji.social_credit));
+        }
+        body.push_str("</table>");
+    }
+
+    Some(layout(&room_id, &body))
+}
+
+pub fn user_page(conn: &Arc<Mutex<Connection>>, room_id: &str, user_id: i32) -> Option<String> {
+    let room_id = room_id.to_string();
+    let user = find_user_by_id_in_db(conn, user_id)?;
+    let room_data = find_user_room_data_by_user_id_and_room_id(conn, user.id, &room_id).ok()?;
+    let transactions = find_transactions_for_user_room_data(conn, room_data.id).unwrap_or_default();
+
+    let mut body = format!(
+        "<p><a href=\"{}\">Back to {}</a></p><h1>{} <small>{}</small></h1><p>Social Credit: <b>{}</b></p><h2>Score over time</h2>",
+        room_link(&room_id), escape_html(&room_id), escape_html(&user.name), escape_html(&user.mxid), room_data.social_credit
+    );
+
+    if transactions.is_empty() {
+        body.push_str("<p>No changes recorded yet</p>");
+        return Some(layout(&user.name, &body));
+    }
+
+    body.push_str(&render_score_chart(&transactions, room_data.social_credit));
+
+    body.push_str("<h2>History</h2><table><tr><th>Time</th><th>Type</th><th>By</th><th>Reason</th><th class=\"num\">Change</th><th class=\"num\">Social Credit</th></tr>");
+    let mut sender_names: HashMap<i32, String> = HashMap::new();
+    for transaction in transactions.iter().rev() {
+        let sender_name = match transaction.sender_user_id {
+            Some(sender_id) => sender_names.entry(sender_id)
+                .or_insert_with(|| find_user_by_id_in_db(conn, sender_id).map_or(String::from("unknown"), |sender| sender.mxid))
+                .clone(),
+            None => String::from("System"),
+        };
+        body.push_str(&format!(
+            "<tr id=\"transaction-{}\"><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td class=\"num\">{:+}</td><td class=\"num\">{}</td></tr>",
+            transaction.id,
+            format_time(transaction.time),
+            escape_html(&transaction.kind),
+            escape_html(&sender_name),
+            escape_html(transaction.reason.as_deref().unwrap_or("")),
+            transaction.delta,
+            transaction.social_credit
+        ));
+    }
+    body.push_str("</table>");
+
+    Some(layout(&user.name, &body))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::open_database;
+    use crate::data::user::UserType;
+    use crate::utils::user_util::setup_user;
+
+    #[test]
+    fn room_page_is_paginated() {
+        let conn = Arc::new(Mutex::new(open_database(":memory:")));
+        let room_id = "!room:example.org";
+        for index in 0..LEADERBOARD_PAGE_SIZE + 1 {
+            setup_user(&conn, Some(room_id), &format!("@user{}:example.org", index), UserType::Default, index as i32).unwrap();
+        }
+        setup_user(&conn, Some(room_id), &String::from("@bot:example.org"), UserType::Default, 1000).unwrap();
+
+        let first_page = room_page(&conn, room_id, "@bot:example.org", 1).unwrap();
+        assert!(first_page.contains(&format!("@user{}:example.org", LEADERBOARD_PAGE_SIZE)));
+        assert!(!first_page.contains("@user0:example.org"));
+        assert!(!first_page.contains("@bot:example.org"));
+        assert!(first_page.contains("Page 1 of 2"));
+
+        let second_page = room_page(&conn, room_id, "@bot:example.org", 2).unwrap();
+        assert!(second_page.contains("@user0:example.org"));
+        assert!(room_page(&conn, room_id, "@bot:example.org", 3).is_none());
+        assert!(index_page(&conn, "@bot:example.org").contains(&format!("<td class=\"num\">{}</td>", LEADERBOARD_PAGE_SIZE + 1)));
+    }
+}